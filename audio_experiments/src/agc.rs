@@ -0,0 +1,176 @@
+use std::time::Duration;
+use crate::format::Sample;
+use crate::frame::Frame;
+use crate::meter::to_dbfs;
+
+/// Settings of an [`Agc`]. Levels are in dBFS.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AgcConfig {
+    /// The RMS level to bring the audio to.
+    pub target_db: f32,
+    /// How fast the gain comes down when the audio gets louder.
+    pub attack: Duration,
+    /// How fast the gain goes back up when the audio gets quieter.
+    pub release: Duration,
+    /// Below this RMS level the audio counts as noise and is muted. The gain stays where it was,
+    /// so it doesn't blast the first moments of audio once it comes back.
+    pub gate_db: f32,
+    /// The most the audio is ever amplified.
+    pub max_gain_db: f32,
+}
+
+impl Default for AgcConfig {
+    fn default() -> Self {
+        AgcConfig {
+            target_db: -20.0,
+            attack: Duration::from_millis(50),
+            release: Duration::from_millis(1000),
+            gate_db: -60.0,
+            max_gain_db: 30.0,
+        }
+    }
+}
+
+/// Automatic gain control with a noise gate. Brings whatever comes in to a steady level, the
+/// same gain for every channel so the stereo image stays put. Gain changes are ramped over the
+/// frame, so they don't click.
+#[derive(Debug, Clone)]
+pub struct Agc {
+    config: AgcConfig,
+    /// Smoothed RMS of the input, linear.
+    envelope: f32,
+    /// Gain applied at the end of the last frame, linear.
+    gain: f32,
+    gate_open: bool,
+}
+
+impl Agc {
+    pub fn new(config: AgcConfig) -> Self {
+        Agc {
+            config,
+            envelope: 0.0,
+            gain: 1.0,
+            gate_open: false,
+        }
+    }
+
+    pub fn config(&self) -> AgcConfig {
+        self.config
+    }
+
+    /// The gain currently applied, in dB. Doesn't include the gate.
+    pub fn gain_db(&self) -> f32 {
+        to_dbfs(self.gain)
+    }
+
+    pub fn gate_open(&self) -> bool {
+        self.gate_open
+    }
+
+    /// Applies the gain (and gate) to `frame` in place.
+    pub fn process<T: Sample>(&mut self, frame: &mut Frame<T>) {
+        if frame.is_empty() {
+            return;
+        }
+
+        let mut sum_of_squares = 0.0;
+        for c in 0..frame.channels() as usize {
+            sum_of_squares += frame.channel(c).iter().map(|s| s.to_f32() * s.to_f32()).sum::<f32>();
+        }
+        let rms = (sum_of_squares / (frame.len() * frame.channels() as usize) as f32).sqrt();
+
+        let start_gain = if self.gate_open { self.gain } else { 0.0 };
+        self.gate_open = to_dbfs(rms) >= self.config.gate_db;
+        let end_gain = if self.gate_open {
+            if self.envelope == 0.0 {
+                // Start out at the right level, instead of at full gain.
+                self.envelope = rms;
+            }
+            // One-pole envelope follower, stepped once per frame.
+            let time = if rms > self.envelope { self.config.attack } else { self.config.release };
+            let coefficient = (-frame.duration().as_secs_f32() / time.as_secs_f32().max(f32::EPSILON)).exp();
+            self.envelope = coefficient * self.envelope + (1.0 - coefficient) * rms;
+
+            let max_gain = db_to_gain(self.config.max_gain_db);
+            self.gain = (db_to_gain(self.config.target_db) / self.envelope).min(max_gain);
+            self.gain
+        } else {
+            0.0
+        };
+
+        let len = frame.len();
+        for c in 0..frame.channels() as usize {
+            for (i, sample) in frame.channel_mut(c).iter_mut().enumerate() {
+                let gain = start_gain + (end_gain - start_gain) * (i + 1) as f32 / len as f32;
+                *sample = T::from_f32((sample.to_f32() * gain).clamp(-1.0, 1.0));
+            }
+        }
+    }
+}
+
+impl Default for Agc {
+    fn default() -> Self {
+        Agc::new(AgcConfig::default())
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine_frame(amplitude: f32, index: u64) -> Frame<f32> {
+        let samples: Vec<f32> = (0..480)
+            .map(|i| amplitude * (2.0 * std::f32::consts::PI * i as f32 / 48.0).sin())
+            .collect();
+        Frame::new(&samples, 1, 48000).with_timing(index * 480, std::time::Instant::now())
+    }
+
+    fn rms_db(frame: &Frame<f32>) -> f32 {
+        to_dbfs((frame.channel(0).iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt())
+    }
+
+    #[test]
+    fn levels_out_and_gates() {
+        let mut agc = Agc::default();
+
+        // Quiet audio (-43 dBFS) is brought up to the target, fading in as the gate opens.
+        let mut frame = sine_frame(0.01, 0);
+        for i in 0..10 {
+            frame = sine_frame(0.01, i);
+            agc.process(&mut frame);
+        }
+        assert!((rms_db(&frame) - -20.0).abs() < 0.5, "{} dBFS", rms_db(&frame));
+        assert!(agc.gate_open());
+
+        // Loud audio (-3 dBFS) is brought down within a few attack times...
+        for i in 10..40 {
+            frame = sine_frame(1.0, i);
+            agc.process(&mut frame);
+        }
+        assert!((rms_db(&frame) - -20.0).abs() < 0.5, "{} dBFS", rms_db(&frame));
+
+        // ...while coming back up from a drop that size takes several release times.
+        frame = sine_frame(0.01, 40);
+        agc.process(&mut frame);
+        assert!(rms_db(&frame) < -30.0, "{} dBFS", rms_db(&frame));
+        for i in 41..1000 {
+            frame = sine_frame(0.01, i);
+            agc.process(&mut frame);
+        }
+        assert!((rms_db(&frame) - -20.0).abs() < 0.5, "{} dBFS", rms_db(&frame));
+
+        // Noise below the gate is muted, without touching the gain.
+        let gain = agc.gain_db();
+        let mut noise = sine_frame(0.0005, 1000);
+        agc.process(&mut noise);
+        noise = sine_frame(0.0005, 1001);
+        agc.process(&mut noise);
+        assert!(!agc.gate_open());
+        assert!(noise.channel(0).iter().all(|&s| s == 0.0));
+        assert_eq!(agc.gain_db(), gain);
+    }
+}
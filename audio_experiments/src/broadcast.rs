@@ -0,0 +1,385 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak, atomic::{AtomicBool, Ordering}, mpsc::{RecvError, RecvTimeoutError, TryRecvError}};
+use std::time::{Duration, Instant};
+use crate::sync::{ignore_poison, lock};
+
+/// How often a consumer thread checks whether it should stop while no audio is coming in, see
+/// [`Subscription::recv_while`].
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What happens when a subscriber's queue is full and a new frame comes in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Throw away the oldest queued frame to make room, so the subscriber always gets the most
+    /// recent audio.
+    DropOldest,
+    /// Throw away the new frame, so the subscriber sees an uninterrupted run of old audio.
+    DropNewest,
+    /// Wait until the subscriber makes room. This stalls every other subscriber too, so only use
+    /// it for consumers that must not miss anything (like a recorder) and keep up on average.
+    Block,
+}
+
+struct QueueState<T> {
+    frames: VecDeque<T>,
+    dropped: u64,
+    /// No more frames are coming.
+    closed: bool,
+    /// The [`Subscription`] is gone, nobody will read from the queue anymore.
+    abandoned: bool,
+    /// Nobody has read from the queue yet. Until someone does, it only keeps the newest frames,
+    /// without counting drops or holding anyone up.
+    standby: bool,
+}
+
+struct Queue<T> {
+    state: Mutex<QueueState<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    policy: Backpressure,
+    /// The broadcaster's, to tell it when the queue leaves standby.
+    shared: Arc<Shared>,
+}
+
+impl<T> Queue<T> {
+    fn lock(&self) -> MutexGuard<'_, QueueState<T>> {
+        lock(&self.state)
+    }
+
+    /// Queues `item` according to the backpressure policy.
+    fn push(&self, item: T) {
+        let mut state = self.lock();
+
+        if state.frames.len() >= self.capacity && state.standby {
+            state.frames.pop_front();
+        } else if state.frames.len() >= self.capacity {
+            match self.policy {
+                Backpressure::DropOldest => {
+                    state.frames.pop_front();
+                    state.dropped += 1;
+                }
+                Backpressure::DropNewest => {
+                    state.dropped += 1;
+                    return;
+                }
+                Backpressure::Block => {
+                    while state.frames.len() >= self.capacity && !state.closed && !state.abandoned {
+                        state = ignore_poison(self.not_full.wait(state));
+                    }
+                    if state.closed || state.abandoned {
+                        return;
+                    }
+                }
+            }
+        }
+
+        state.frames.push_back(item);
+        self.not_empty.notify_one();
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+
+    /// Whether a subscriber is reading from the queue.
+    fn is_active(&self) -> bool {
+        let state = self.lock();
+        !state.standby && !state.abandoned
+    }
+
+    /// Locks the queue to read from it, taking it out of standby on the first read.
+    fn lock_for_reading(&self) -> MutexGuard<'_, QueueState<T>> {
+        let mut state = self.lock();
+        if !state.standby {
+            return state;
+        }
+        state.standby = false;
+        drop(state);
+        // Notify while holding the closed lock, so wait_for_subscriber can't miss it between
+        // checking the queues and going to sleep.
+        let _closed = lock(&self.shared.closed);
+        self.shared.subscribed.notify_all();
+        self.lock()
+    }
+}
+
+/// An independent stream of frames from an [`AudioCapturer`](crate::AudioCapturer), with its
+/// own queue and [`Backpressure`] policy. Dropping it unsubscribes.
+pub struct Subscription<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T> Subscription<T> {
+    /// Blocks until a frame is available. Fails once capturing has stopped and every queued
+    /// frame has been read.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let mut state = self.queue.lock_for_reading();
+        loop {
+            if let Some(item) = state.frames.pop_front() {
+                self.queue.not_full.notify_one();
+                return Ok(item);
+            }
+            if state.closed {
+                return Err(RecvError);
+            }
+            state = ignore_poison(self.queue.not_empty.wait(state));
+        }
+    }
+
+    /// Like [`recv`](Self::recv), but gives up after `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.queue.lock_for_reading();
+        loop {
+            if let Some(item) = state.frames.pop_front() {
+                self.queue.not_full.notify_one();
+                return Ok(item);
+            }
+            if state.closed {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = ignore_poison(self.queue.not_empty.wait_timeout(state, deadline - now)).0;
+        }
+    }
+
+    /// Blocks until a frame is available, for a thread that runs while `running` is set.
+    /// Returns `None` once it's cleared or capturing has stopped.
+    pub(crate) fn recv_while(&self, running: &AtomicBool) -> Option<T> {
+        while running.load(Ordering::SeqCst) {
+            match self.recv_timeout(POLL_INTERVAL) {
+                Ok(item) => return Some(item),
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+        None
+    }
+
+    /// Returns a queued frame if there is one, without blocking.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.queue.lock_for_reading();
+        match state.frames.pop_front() {
+            Some(item) => {
+                self.queue.not_full.notify_one();
+                Ok(item)
+            }
+            None if state.closed => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// How many frames this subscriber has lost to its backpressure policy.
+    pub fn dropped(&self) -> u64 {
+        self.queue.lock().dropped
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        self.queue.lock().abandoned = true;
+        // Don't leave the reader thread waiting for us.
+        self.queue.not_full.notify_all();
+    }
+}
+
+/// What the broadcaster shares with its queues.
+struct Shared {
+    closed: Mutex<bool>,
+    /// Signalled when someone subscribes, a queue leaves standby, or the broadcaster is closed.
+    subscribed: Condvar,
+}
+
+/// Hands every frame to every live subscription.
+pub(crate) struct Broadcaster<T> {
+    queues: Mutex<Vec<Weak<Queue<T>>>>,
+    shared: Arc<Shared>,
+    /// Reused by send(), so sending doesn't allocate.
+    snapshot: Mutex<Vec<Arc<Queue<T>>>>,
+}
+
+impl<T: Clone> Broadcaster<T> {
+    pub(crate) fn new() -> Self {
+        Broadcaster {
+            queues: Mutex::new(Vec::new()),
+            shared: Arc::new(Shared {
+                closed: Mutex::new(false),
+                subscribed: Condvar::new(),
+            }),
+            snapshot: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn subscribe(&self, capacity: usize, policy: Backpressure) -> Subscription<T> {
+        self.add(capacity, policy, false)
+    }
+
+    /// Like [`subscribe`](Self::subscribe), but the subscription stays on standby until it is
+    /// first read from: it keeps the newest frames, and
+    /// [`wait_for_subscriber`](Self::wait_for_subscriber) doesn't count it. So it's there from
+    /// the start, without holding up the others if nobody ever reads it.
+    pub(crate) fn subscribe_standby(&self, capacity: usize, policy: Backpressure) -> Subscription<T> {
+        self.add(capacity, policy, true)
+    }
+
+    fn add(&self, capacity: usize, policy: Backpressure, standby: bool) -> Subscription<T> {
+        let queue = Arc::new(Queue {
+            state: Mutex::new(QueueState {
+                frames: VecDeque::with_capacity(capacity.max(1)),
+                dropped: 0,
+                closed: false,
+                abandoned: false,
+                standby,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity: capacity.max(1),
+            policy,
+            shared: Arc::clone(&self.shared),
+        });
+
+        // Hold the closed lock while registering, so a subscriber can't slip in after close().
+        let closed = lock(&self.shared.closed);
+        if *closed {
+            queue.close();
+        } else {
+            lock(&self.queues).push(Arc::downgrade(&queue));
+            self.shared.subscribed.notify_all();
+        }
+
+        Subscription { queue }
+    }
+
+    /// Sends `item` to every subscriber. Returns false if there are no subscribers left.
+    pub(crate) fn send(&self, item: T) -> bool {
+        // Take a snapshot so a Block subscriber doesn't keep others from (un)subscribing.
+        let mut snapshot = lock(&self.snapshot);
+        {
+            let mut queues = lock(&self.queues);
+            queues.retain(|queue| queue.strong_count() > 0);
+            snapshot.extend(queues.iter().filter_map(Weak::upgrade));
+        }
+
+        let Some(last) = snapshot.pop() else {
+            return false;
+        };
+        for queue in snapshot.drain(..) {
+            queue.push(item.clone());
+        }
+        last.push(item);
+
+        true
+    }
+
+    /// Blocks until there is at least one subscriber that isn't on standby. Returns false if the
+    /// broadcaster was closed instead.
+    pub(crate) fn wait_for_subscriber(&self) -> bool {
+        let mut closed = lock(&self.shared.closed);
+        loop {
+            if *closed {
+                return false;
+            }
+            {
+                let mut queues = lock(&self.queues);
+                queues.retain(|queue| queue.strong_count() > 0);
+                if queues.iter().filter_map(Weak::upgrade).any(|queue| queue.is_active()) {
+                    return true;
+                }
+            }
+            closed = ignore_poison(self.shared.subscribed.wait(closed));
+        }
+    }
+
+    /// Ends every subscription once its queued frames are read.
+    pub(crate) fn close(&self) {
+        let mut closed = lock(&self.shared.closed);
+        *closed = true;
+        self.shared.subscribed.notify_all();
+        for queue in lock(&self.queues).drain(..) {
+            if let Some(queue) = queue.upgrade() {
+                queue.close();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backpressure_policies() {
+        let broadcaster = Broadcaster::new();
+        let oldest = broadcaster.subscribe(2, Backpressure::DropOldest);
+        let newest = broadcaster.subscribe(2, Backpressure::DropNewest);
+
+        for i in 0..5 {
+            broadcaster.send(i);
+        }
+        broadcaster.close();
+
+        assert_eq!(std::iter::from_fn(|| oldest.recv().ok()).collect::<Vec<_>>(), vec![3, 4]);
+        assert_eq!(std::iter::from_fn(|| newest.recv().ok()).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(oldest.dropped(), 3);
+        assert_eq!(newest.dropped(), 3);
+    }
+
+    #[test]
+    fn block_waits_for_the_subscriber() {
+        let broadcaster = Arc::new(Broadcaster::new());
+        let blocking = broadcaster.subscribe(1, Backpressure::Block);
+
+        let sender = {
+            let broadcaster = Arc::clone(&broadcaster);
+            std::thread::spawn(move || {
+                for i in 0..100 {
+                    broadcaster.send(i);
+                }
+                broadcaster.close();
+            })
+        };
+
+        let received: Vec<i32> = std::iter::from_fn(|| blocking.recv().ok()).collect();
+        sender.join().unwrap();
+
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+        assert_eq!(blocking.dropped(), 0);
+    }
+
+    #[test]
+    fn dropping_a_subscription_unsubscribes() {
+        let broadcaster = Broadcaster::new();
+        drop(broadcaster.subscribe(1, Backpressure::Block));
+        assert!(!broadcaster.send(0));
+    }
+
+    #[test]
+    fn standby_until_the_first_read() {
+        let broadcaster = Arc::new(Broadcaster::new());
+        let standby = broadcaster.subscribe_standby(2, Backpressure::Block);
+
+        // Nothing waits for it, and it only keeps the newest frames.
+        for i in 0..5 {
+            broadcaster.send(i);
+        }
+        assert_eq!(standby.dropped(), 0);
+
+        let waiter = {
+            let broadcaster = Arc::clone(&broadcaster);
+            std::thread::spawn(move || broadcaster.wait_for_subscriber())
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!waiter.is_finished());
+
+        // Reading takes it off standby, and from then on it blocks like any other.
+        assert_eq!(standby.recv(), Ok(3));
+        assert!(waiter.join().unwrap());
+        assert_eq!(standby.recv(), Ok(4));
+    }
+}
@@ -0,0 +1,189 @@
+use std::process::{Command, Stdio};
+
+/// The ffmpeg input device (`-f`) used to open a capture device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Alsa,
+    Pulse,
+}
+
+impl InputFormat {
+    pub fn ffmpeg_name(self) -> &'static str {
+        match self {
+            InputFormat::Alsa => "alsa",
+            InputFormat::Pulse => "pulse",
+        }
+    }
+}
+
+/// Something ffmpeg can record from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureDevice {
+    pub input: InputFormat,
+    /// What to pass to ffmpeg's `-i`, like `hw:1,0` or `alsa_output.pci-0000_00_1f.3.analog-stereo.monitor`.
+    pub name: String,
+    /// Something a human can recognize the device by.
+    pub description: String,
+}
+
+impl CaptureDevice {
+    pub fn new(input: InputFormat, name: impl Into<String>) -> Self {
+        let name = name.into();
+        CaptureDevice {
+            input,
+            description: name.clone(),
+            name,
+        }
+    }
+
+    /// Whether the device is known to exist. `None` if that can't be checked, which is the case
+    /// for `default` and other names that aren't listed by [`list_capture_devices`].
+    pub fn exists(&self) -> Option<bool> {
+        match self.input {
+            InputFormat::Alsa => {
+                let (card, device) = parse_alsa_hw_name(&self.name)?;
+                let pcm = std::fs::read_to_string("/proc/asound/pcm").ok()?;
+                Some(parse_proc_pcm(&pcm).contains(&(card, device)))
+            }
+            InputFormat::Pulse => {
+                if self.name == "default" {
+                    return None;
+                }
+                let sources = pulse_sources()?;
+                Some(sources.iter().any(|source| source.name == self.name))
+            }
+        }
+    }
+}
+
+impl Default for CaptureDevice {
+    /// ALSA's `default` device, which is usually the desktop audio.
+    fn default() -> Self {
+        CaptureDevice {
+            input: InputFormat::Alsa,
+            name: "default".into(),
+            description: "Default ALSA device".into(),
+        }
+    }
+}
+
+/// Lists what can be recorded from: ALSA capture devices (as `hw` and `plughw` names) and
+/// PulseAudio sources, including the monitors of outputs. PulseAudio sources are listed through
+/// `ffmpeg -sources pulse`, so they are missing when ffmpeg or PulseAudio isn't available.
+pub fn list_capture_devices() -> Vec<CaptureDevice> {
+    let mut devices = vec![CaptureDevice::default()];
+
+    if let Ok(pcm) = std::fs::read_to_string("/proc/asound/pcm") {
+        for (card, device) in parse_proc_pcm(&pcm) {
+            for prefix in ["hw", "plughw"] {
+                devices.push(CaptureDevice {
+                    input: InputFormat::Alsa,
+                    name: format!("{}:{},{}", prefix, card, device),
+                    description: pcm_description(&pcm, card, device),
+                });
+            }
+        }
+    }
+
+    devices.extend(pulse_sources().unwrap_or_default());
+    devices
+}
+
+fn pulse_sources() -> Option<Vec<CaptureDevice>> {
+    let output = Command::new("ffmpeg")
+        .arg("-hide_banner")
+        .arg("-sources").arg("pulse")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    // ffmpeg fails when it can't list them (say, without PulseAudio), which is not the same as
+    // there being none.
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(parse_ffmpeg_sources(&String::from_utf8_lossy(&output.stdout), InputFormat::Pulse))
+}
+
+/// Parses `/proc/asound/pcm` into the `(card, device)` pairs that can capture. Lines look like
+/// `00-00: ALC892 Analog : ALC892 Analog : playback 1 : capture 1`.
+fn parse_proc_pcm(pcm: &str) -> Vec<(u32, u32)> {
+    pcm.lines()
+        .filter(|line| line.split(':').any(|field| field.trim().starts_with("capture")))
+        .filter_map(|line| {
+            let (card, device) = line.split(':').next()?.split_once('-')?;
+            Some((card.trim().parse().ok()?, device.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+fn pcm_description(pcm: &str, card: u32, device: u32) -> String {
+    let id = format!("{:02}-{:02}:", card, device);
+    pcm.lines()
+        .find_map(|line| line.strip_prefix(&id))
+        .and_then(|rest| rest.split(':').next())
+        .map(|name| name.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Parses `hw:1`, `hw:1,0`, `plughw:1,0` and the like into `(card, device)`.
+fn parse_alsa_hw_name(name: &str) -> Option<(u32, u32)> {
+    let rest = name.strip_prefix("hw:").or_else(|| name.strip_prefix("plughw:"))?;
+    match rest.split_once(',') {
+        Some((card, device)) => Some((card.parse().ok()?, device.parse().ok()?)),
+        None => Some((rest.parse().ok()?, 0)),
+    }
+}
+
+/// Parses the output of `ffmpeg -sources <input>`, which looks like
+///
+/// ```text
+/// Auto-detected sources for pulse:
+/// * alsa_output.pci-0000_00_1f.3.analog-stereo.monitor [Monitor of Built-in Audio Analog Stereo]
+///   alsa_input.pci-0000_00_1f.3.analog-stereo [Built-in Audio Analog Stereo]
+/// ```
+fn parse_ffmpeg_sources(output: &str, input: InputFormat) -> Vec<CaptureDevice> {
+    output.lines()
+        .filter(|line| line.starts_with(' ') || line.starts_with('*'))
+        .filter_map(|line| {
+            let line = line.trim_start_matches('*').trim();
+            let (name, description) = match line.split_once(" [") {
+                Some((name, description)) => (name, description.trim_end_matches(']')),
+                None => (line, line),
+            };
+            (!name.is_empty()).then(|| CaptureDevice {
+                input,
+                name: name.to_string(),
+                description: description.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_device_listings() {
+        let pcm = "00-00: ALC892 Analog : ALC892 Analog : playback 1 : capture 1\n\
+                   00-01: ALC892 Digital : ALC892 Digital : playback 1\n\
+                   01-03: HDMI 0 : HDMI 0 : playback 1\n\
+                   02-00: USB Audio : USB Audio : capture 1\n";
+        assert_eq!(parse_proc_pcm(pcm), vec![(0, 0), (2, 0)]);
+        assert_eq!(pcm_description(pcm, 2, 0), "USB Audio");
+        assert_eq!(parse_alsa_hw_name("plughw:2,0"), Some((2, 0)));
+        assert_eq!(parse_alsa_hw_name("hw:1"), Some((1, 0)));
+        assert_eq!(parse_alsa_hw_name("hw:CARD=PCH"), None);
+
+        let sources = "Auto-detected sources for pulse:\n\
+                       * alsa_output.pci.analog-stereo.monitor [Monitor of Built-in Audio]\n  \
+                       alsa_input.pci.analog-stereo [Built-in Audio]\n";
+        let devices = parse_ffmpeg_sources(sources, InputFormat::Pulse);
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].name, "alsa_output.pci.analog-stereo.monitor");
+        assert_eq!(devices[0].description, "Monitor of Built-in Audio");
+        assert_eq!(devices[1].name, "alsa_input.pci.analog-stereo");
+    }
+}
@@ -0,0 +1,376 @@
+use std::collections::VecDeque;
+use std::ffi::{OsStr, OsString};
+use std::io::{BufRead, BufReader};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
+use std::thread::JoinHandle;
+use crate::devices::CaptureDevice;
+use crate::format::CaptureConfig;
+use crate::pcm::PcmSource;
+use crate::source::{AudioSource, StopHandle};
+use crate::sync::lock;
+use crate::AudioCaptureError;
+
+/// How many lines of ffmpeg's stderr to hold on to for error reports.
+const STDERR_LINES: usize = 20;
+
+/// The program an [`FfmpegSource`] runs and the arguments it passes. Arguments may contain
+/// placeholders that are filled in when the source starts:
+///
+/// - `{input_format}`: the device's ffmpeg input format, like `alsa`
+/// - `{device}`: the device name
+/// - `{sample_rate}`, `{channels}`: from the [`CaptureConfig`]
+/// - `{format}`: the raw PCM output format, like `s16le`
+///
+/// Whatever the program is, it has to write raw PCM in the requested format to stdout. Pointing
+/// it at a stub script makes it possible to test without ffmpeg or a sound card.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FfmpegCommand {
+    pub program: OsString,
+    pub args: Vec<String>,
+}
+
+impl FfmpegCommand {
+    pub fn new(program: impl Into<OsString>, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        FfmpegCommand {
+            program: program.into(),
+            args: args.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// The arguments with every placeholder filled in.
+    pub fn render_args(&self, device: &CaptureDevice, config: CaptureConfig) -> Vec<String> {
+        self.args.iter()
+            .map(|arg| {
+                arg.replace("{input_format}", device.input.ffmpeg_name())
+                    .replace("{device}", &device.name)
+                    .replace("{sample_rate}", &config.sample_rate.to_string())
+                    .replace("{channels}", &config.channels.to_string())
+                    .replace("{format}", config.format.ffmpeg_name())
+            })
+            .collect()
+    }
+}
+
+impl Default for FfmpegCommand {
+    /// `ffmpeg` from the `PATH`, recording the device and piping raw PCM to stdout.
+    fn default() -> Self {
+        FfmpegCommand::new("ffmpeg", [
+            "-f", "{input_format}", // Input format
+            "-i", "{device}", // Recording device
+            "-ar", "{sample_rate}", // Output sample rate
+            "-ac", "{channels}", // Output channel count
+            "-f", "{format}", // Output format (raw PCM)
+            "-v", "error", // Only errors end up on stderr
+            "-nostdin",
+            "pipe:1", // Pipe PCM audio to stdout
+        ])
+    }
+}
+
+/// Records a capture device through an ffmpeg child process.
+pub struct FfmpegSource {
+    ffmpeg: Arc<Mutex<Child>>,
+    stdout: PcmSource<ChildStdout>,
+    stderr: Arc<Mutex<VecDeque<String>>>,
+    stderr_thread: Option<JoinHandle<()>>,
+    device: String,
+    config: CaptureConfig,
+    stopped: Arc<AtomicBool>,
+    received_audio: bool,
+}
+
+impl FfmpegSource {
+    /// ffmpeg resamples and remixes whatever the device delivers to match `config`. Fails with
+    /// [`AudioCaptureError::DeviceNotFound`] for devices that are known not to exist.
+    pub fn new(device: &CaptureDevice, config: CaptureConfig) -> Result<Self, AudioCaptureError> {
+        if device.exists() == Some(false) {
+            return Err(AudioCaptureError::DeviceNotFound(device.name.clone()));
+        }
+
+        Self::with_command(&FfmpegCommand::default(), device, config)
+    }
+
+    /// Like [`new`](Self::new), but runs `command` instead of the default ffmpeg invocation.
+    /// `device` is passed on as is, without checking whether it exists, since that would take
+    /// ffmpeg itself.
+    pub fn with_command(command: &FfmpegCommand, device: &CaptureDevice, config: CaptureConfig) -> Result<Self, AudioCaptureError> {
+        Self::spawn(&command.program, command.render_args(device, config), &device.name, config)
+    }
+
+    /// Runs `program`, which has to write raw PCM in `config`'s format to stdout. `input` names
+    /// what it reads from in error reports.
+    pub(crate) fn spawn(program: &OsStr, args: Vec<String>, input: &str, config: CaptureConfig) -> Result<Self, AudioCaptureError> {
+        let mut ffmpeg = Command::new(program)
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()) // Collected for error reports
+            .stdin(Stdio::null())
+            .spawn()?;
+
+        let stdout = ffmpeg.stdout.take().ok_or(AudioCaptureError::NoStdout)?;
+
+        // Keep the tail of stderr around. Reading it continuously also keeps ffmpeg from
+        // blocking on a full pipe.
+        let stderr = Arc::new(Mutex::new(VecDeque::with_capacity(STDERR_LINES)));
+        let stderr_thread = ffmpeg.stderr.take().map(|pipe| {
+            let stderr = Arc::clone(&stderr);
+            std::thread::spawn(move || {
+                for line in BufReader::new(pipe).lines() {
+                    let Ok(line) = line else { break };
+                    let mut stderr = lock(&stderr);
+                    if stderr.len() == STDERR_LINES {
+                        stderr.pop_front();
+                    }
+                    stderr.push_back(line);
+                }
+            })
+        });
+
+        Ok(FfmpegSource {
+            ffmpeg: Arc::new(Mutex::new(ffmpeg)),
+            stdout: PcmSource::new(stdout, config),
+            stderr,
+            stderr_thread,
+            device: input.to_string(),
+            config,
+            stopped: Arc::new(AtomicBool::new(false)),
+            received_audio: false,
+        })
+    }
+
+    /// The last few lines ffmpeg wrote to stderr.
+    pub fn diagnostics(&self) -> String {
+        let stderr = lock(&self.stderr);
+        stderr.iter().map(String::as_str).collect::<Vec<_>>().join("\n")
+    }
+
+    /// Works out why ffmpeg's stdout ran dry.
+    fn exit_error(&mut self, read_error: AudioCaptureError) -> AudioCaptureError {
+        if self.stopped.load(Ordering::SeqCst) {
+            return AudioCaptureError::Stopped;
+        }
+
+        // Poll rather than wait(), so the stop handle can still get at the child meanwhile.
+        let status = loop {
+            let status = lock(&self.ffmpeg).try_wait();
+            match status {
+                Ok(Some(status)) => break status,
+                Ok(None) => std::thread::sleep(std::time::Duration::from_millis(10)),
+                Err(..) => return read_error,
+            }
+        };
+        if self.stopped.load(Ordering::SeqCst) {
+            return AudioCaptureError::Stopped;
+        }
+
+        // ffmpeg has exited, so stderr is closed and the thread is about to finish.
+        if let Some(thread) = self.stderr_thread.take() {
+            let _ = thread.join();
+        }
+        let stderr = self.diagnostics();
+
+        if status.success() {
+            AudioCaptureError::EndOfStream
+        } else if !self.received_audio {
+            // ffmpeg gave up before producing anything, which means the input couldn't be opened.
+            AudioCaptureError::DeviceOpen {
+                device: self.device.clone(),
+                stderr,
+            }
+        } else {
+            AudioCaptureError::ProcessExited { status, stderr }
+        }
+    }
+}
+
+impl AudioSource for FfmpegSource {
+    fn config(&self) -> CaptureConfig {
+        self.config
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), AudioCaptureError> {
+        match self.stdout.read_exact(buf) {
+            Ok(()) => {
+                self.received_audio = true;
+                Ok(())
+            }
+            Err(e) => Err(self.exit_error(e)),
+        }
+    }
+
+    fn stop_handle(&self) -> StopHandle {
+        let ffmpeg = Arc::clone(&self.ffmpeg);
+        let stopped = Arc::clone(&self.stopped);
+        // Killing ffmpeg closes its stdout, which unblocks a pending read.
+        StopHandle::new(move || {
+            stopped.store(true, Ordering::SeqCst);
+            if let Ok(mut ffmpeg) = ffmpeg.lock() {
+                let _ = ffmpeg.kill();
+            }
+        })
+    }
+}
+
+impl Drop for FfmpegSource {
+    fn drop(&mut self) {
+        if let Ok(mut ffmpeg) = self.ffmpeg.lock() {
+            let _ = ffmpeg.kill();
+            // Reap it, so it doesn't linger as a zombie.
+            let _ = ffmpeg.wait();
+        }
+        // With ffmpeg gone its stderr is closed, so the collector is done.
+        if let Some(thread) = self.stderr_thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::InputFormat;
+    use crate::format::SampleFormat;
+    use crate::reader::FrameReader;
+    use crate::test_util::temp_path;
+    use crate::AudioCapturer;
+    use std::path::{Path, PathBuf};
+
+    const CONFIG: CaptureConfig = CaptureConfig { sample_rate: 8000, channels: 2, format: SampleFormat::S16 };
+
+    /// A stand-in for ffmpeg: a shell script that gets the rendered arguments as `$1`, `$2`, ...
+    fn stub(script: &str) -> FfmpegCommand {
+        FfmpegCommand::new("sh", ["-c", script, "stub", "{input_format}", "{device}", "{sample_rate}", "{channels}", "{format}"])
+    }
+
+    fn capture(script: &str, buffer_size: usize) -> AudioCapturer<i16> {
+        let device = CaptureDevice::new(InputFormat::Alsa, "stub");
+        let source = FfmpegSource::with_command(&stub(script), &device, CONFIG).unwrap();
+        AudioCapturer::with_source(source, buffer_size).unwrap()
+    }
+
+    /// Writes interleaved s16le samples to a temp file the stub can `cat`.
+    fn pcm_file(name: &str, samples: &[i16]) -> PathBuf {
+        let path = temp_path(&format!("{}.pcm", name));
+        std::fs::write(&path, samples.iter().flat_map(|s| s.to_le_bytes()).collect::<Vec<_>>()).unwrap();
+        path
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        let device = CaptureDevice::new(InputFormat::Pulse, "monitor");
+        let args = FfmpegCommand::default().render_args(&device, CONFIG);
+        assert_eq!(args[..10], ["-f", "pulse", "-i", "monitor", "-ar", "8000", "-ac", "2", "-f", "s16le"]);
+    }
+
+    #[test]
+    fn frames_and_partial_read_at_eof() {
+        // Two and a half frames of 3 stereo samples.
+        let samples: Vec<i16> = (0..15).collect();
+        let path = pcm_file("frames", &samples);
+        let device = CaptureDevice::new(InputFormat::Alsa, "stub");
+        let command = stub(&format!("cat '{}'", path.display()));
+        let mut source = FfmpegSource::with_command(&command, &device, CONFIG).unwrap();
+
+        // Reading straight from the source, since a capturer would drop frames nobody picked up
+        // in time.
+        let mut reader = FrameReader::<i16>::new(CONFIG, 3);
+        let first = reader.read(&mut source).unwrap();
+        assert_eq!(first.left(), &[0, 2, 4]);
+        assert_eq!(first.right(), &[1, 3, 5]);
+        let second = reader.read(&mut source).unwrap();
+        assert_eq!(second.interleaved().collect::<Vec<_>>(), (6..12).collect::<Vec<_>>());
+        assert_eq!(second.sample_index(), 3);
+
+        // The half frame at the end is discarded and the stub exited cleanly.
+        assert!(matches!(reader.read(&mut source), Err(AudioCaptureError::EndOfStream)));
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn pulse_devices_are_left_to_the_command() {
+        // Not looked up with the real ffmpeg, which doesn't know about it.
+        let device = CaptureDevice::new(InputFormat::Pulse, "stub");
+        let source = FfmpegSource::with_command(&stub("[ \"$1 $2\" = 'pulse stub' ] && exec cat /dev/zero"), &device, CONFIG).unwrap();
+        let capturer = AudioCapturer::<i16>::with_source(source, 64).unwrap();
+        assert!(capturer.read_frame().unwrap().interleaved().all(|sample| sample == 0));
+    }
+
+    #[test]
+    fn stop_kills_the_process() {
+        let pid_file = temp_path("stop.pid");
+        let mut capturer = capture(&format!("echo $$ > '{}'; exec cat /dev/zero", pid_file.display()), 64);
+        capturer.read_frame().unwrap();
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+
+        capturer.stop().unwrap();
+        // Reaped, not left behind as a zombie.
+        assert!(!Path::new(&format!("/proc/{}", pid.trim())).exists());
+        capturer.stop().unwrap();
+
+        // Drain whatever was already queued.
+        let error = loop {
+            if let Err(e) = capturer.read_frame() {
+                break e;
+            }
+        };
+        assert!(matches!(error, AudioCaptureError::Stopped));
+
+        let _ = std::fs::remove_file(pid_file);
+    }
+
+    #[test]
+    fn stop_reports_how_the_process_ended() {
+        let mut capturer = capture("echo \"$2: Device or resource busy\" >&2; exit 1", 64);
+        // Wait for capturing to end, without taking the error like read_frame would.
+        let watcher = capturer.subscribe(1, crate::Backpressure::DropOldest);
+        while watcher.recv().is_ok() {}
+
+        match capturer.stop() {
+            Err(AudioCaptureError::DeviceOpen { stderr, .. }) => assert_eq!(stderr, "stub: Device or resource busy"),
+            other => panic!("expected DeviceOpen, got {:?}", other),
+        }
+        assert!(capturer.stop().is_ok());
+    }
+
+    #[test]
+    fn failing_to_open_the_device() {
+        let capturer = capture("echo \"$2: No such device ($1, $3 Hz, $4 channels, $5)\" >&2; exit 1", 64);
+
+        match capturer.read_frame() {
+            Err(AudioCaptureError::DeviceOpen { device, stderr }) => {
+                assert_eq!(device, "stub");
+                assert_eq!(stderr, "stub: No such device (alsa, 8000 Hz, 2 channels, s16le)");
+            }
+            other => panic!("expected DeviceOpen, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn process_dying_mid_stream() {
+        let path = pcm_file("dying", &[0; 8]);
+        let mut capturer = capture(&format!("cat '{}'; echo broken pipe >&2; exit 3", path.display()), 4);
+
+        capturer.read_frame().unwrap();
+        match capturer.read_frame() {
+            Err(AudioCaptureError::ProcessExited { status, stderr }) => {
+                assert_eq!(status.code(), Some(3));
+                assert_eq!(stderr, "broken pipe");
+            }
+            other => panic!("expected ProcessExited, got {:?}", other),
+        }
+        assert!(matches!(capturer.read_frame(), Err(AudioCaptureError::Stopped)));
+        // stop() reports it too, even though read_frame already did.
+        assert!(matches!(capturer.stop(), Err(AudioCaptureError::ProcessExited { .. })));
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn missing_program() {
+        let command = FfmpegCommand::new("/nonexistent/ffmpeg", Vec::<String>::new());
+        let result = FfmpegSource::with_command(&command, &CaptureDevice::default(), CONFIG);
+        assert!(matches!(result, Err(AudioCaptureError::FfmpegError(..))));
+    }
+}
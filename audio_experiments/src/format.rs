@@ -0,0 +1,147 @@
+/// Encoding of a single little-endian PCM sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    U8,
+    S16,
+    S24,
+    S32,
+    F32,
+    F64,
+}
+
+impl SampleFormat {
+    pub fn bytes_per_sample(self) -> usize {
+        match self {
+            SampleFormat::U8 => 1,
+            SampleFormat::S16 => 2,
+            SampleFormat::S24 => 3,
+            SampleFormat::S32 | SampleFormat::F32 => 4,
+            SampleFormat::F64 => 8,
+        }
+    }
+
+    /// The name ffmpeg uses for raw PCM in this format (for `-f`).
+    pub fn ffmpeg_name(self) -> &'static str {
+        match self {
+            SampleFormat::U8 => "u8",
+            SampleFormat::S16 => "s16le",
+            SampleFormat::S24 => "s24le",
+            SampleFormat::S32 => "s32le",
+            SampleFormat::F32 => "f32le",
+            SampleFormat::F64 => "f64le",
+        }
+    }
+
+    /// Decodes one sample into the range `-1.0..=1.0`. `bytes` must be exactly
+    /// [`bytes_per_sample`](Self::bytes_per_sample) long.
+    pub fn decode(self, bytes: &[u8]) -> f32 {
+        match self {
+            SampleFormat::U8 => (bytes[0] as f32 - 128.0) / 128.0,
+            SampleFormat::S16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            SampleFormat::S24 => {
+                // Shift into the top of an i32 so the sign bit ends up in the right place.
+                let value = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+                value as f32 / 8_388_608.0
+            }
+            SampleFormat::S32 => {
+                i32::from_le_bytes(bytes.try_into().unwrap()) as f32 / 2_147_483_648.0
+            }
+            SampleFormat::F32 => f32::from_le_bytes(bytes.try_into().unwrap()),
+            SampleFormat::F64 => f64::from_le_bytes(bytes.try_into().unwrap()) as f32,
+        }
+    }
+
+    /// Encodes a sample in the range `-1.0..=1.0` (anything outside is clipped). `out` must be
+    /// exactly [`bytes_per_sample`](Self::bytes_per_sample) long.
+    pub fn encode(self, value: f32, out: &mut [u8]) {
+        let value = value.clamp(-1.0, 1.0);
+        match self {
+            SampleFormat::U8 => out[0] = (value * 128.0 + 128.0).round().min(255.0) as u8,
+            SampleFormat::S16 => out.copy_from_slice(&i16::from_f32(value).to_le_bytes()),
+            SampleFormat::S24 => {
+                let scaled = (value as f64 * 8_388_608.0).round().min(8_388_607.0) as i32;
+                out.copy_from_slice(&scaled.to_le_bytes()[..3]);
+            }
+            SampleFormat::S32 => {
+                let scaled = (value as f64 * 2_147_483_648.0).round().min(2_147_483_647.0) as i32;
+                out.copy_from_slice(&scaled.to_le_bytes());
+            }
+            SampleFormat::F32 => out.copy_from_slice(&value.to_le_bytes()),
+            SampleFormat::F64 => out.copy_from_slice(&(value as f64).to_le_bytes()),
+        }
+    }
+}
+
+/// A sample type frames can be delivered in.
+pub trait Sample: Copy + Default + Send + Sync + std::fmt::Debug + 'static {
+    /// The wire format that holds this type without conversion.
+    const FORMAT: SampleFormat;
+
+    /// Converts from the range `-1.0..=1.0`, clipping anything outside of it.
+    fn from_f32(value: f32) -> Self;
+
+    /// Converts to the range `-1.0..=1.0`.
+    fn to_f32(self) -> f32;
+
+    /// Decodes one raw sample of the given format.
+    fn decode(format: SampleFormat, bytes: &[u8]) -> Self {
+        Self::from_f32(format.decode(bytes))
+    }
+}
+
+impl Sample for i16 {
+    const FORMAT: SampleFormat = SampleFormat::S16;
+
+    fn from_f32(value: f32) -> Self {
+        (value * 32768.0).round().clamp(-32768.0, 32767.0) as i16
+    }
+
+    fn to_f32(self) -> f32 {
+        self as f32 / 32768.0
+    }
+
+    fn decode(format: SampleFormat, bytes: &[u8]) -> Self {
+        match format {
+            SampleFormat::S16 => i16::from_le_bytes([bytes[0], bytes[1]]),
+            _ => Self::from_f32(format.decode(bytes)),
+        }
+    }
+}
+
+impl Sample for f32 {
+    const FORMAT: SampleFormat = SampleFormat::F32;
+
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+}
+
+/// The shape of the audio a source produces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureConfig {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub format: SampleFormat,
+}
+
+impl CaptureConfig {
+    /// Size in bytes of one sample for every channel.
+    pub fn bytes_per_frame(&self) -> usize {
+        self.channels as usize * self.format.bytes_per_sample()
+    }
+}
+
+impl Default for CaptureConfig {
+    /// CD quality stereo.
+    fn default() -> Self {
+        CaptureConfig {
+            sample_rate: 44100,
+            channels: 2,
+            format: SampleFormat::S16,
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use std::time::{Duration, Instant};
+use crate::format::Sample;
+use crate::pool::Buffer;
+
+/// A buffer of audio, along with what's needed to make sense of it.
+///
+/// Samples are stored per channel (all of channel 0, then all of channel 1, ...), so every channel
+/// is available as a plain slice.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame<T: Copy + Default> {
+    data: Buffer<T>,
+    channels: u16,
+    sample_rate: u32,
+    sample_index: u64,
+    captured_at: Instant,
+}
+
+impl<T: Copy + Default> Frame<T> {
+    /// Builds a frame from interleaved samples (`L R L R ...` for stereo). `samples` must hold a
+    /// whole number of sample frames (one sample for every channel).
+    pub fn new(samples: &[T], channels: u16, sample_rate: u32) -> Self {
+        debug_assert!(channels > 0 && samples.len().is_multiple_of(channels as usize));
+        let channel_count = channels as usize;
+        let len = samples.len() / channel_count;
+
+        let data = (0..channel_count)
+            .flat_map(|channel| samples.iter().skip(channel).step_by(channel_count).copied())
+            .collect();
+
+        Frame::from_planar(data, channels, sample_rate, len)
+    }
+
+    /// Builds a frame from samples that are already laid out per channel, `len` samples each.
+    pub fn from_planar(data: Vec<T>, channels: u16, sample_rate: u32, len: usize) -> Self {
+        debug_assert!(channels > 0 && data.len() == len * channels as usize);
+        Frame::from_buffer(Buffer::unpooled(data), channels, sample_rate)
+    }
+
+    pub(crate) fn from_buffer(data: Buffer<T>, channels: u16, sample_rate: u32) -> Self {
+        Frame {
+            data,
+            channels,
+            sample_rate,
+            sample_index: 0,
+            captured_at: Instant::now(),
+        }
+    }
+
+    /// Places the frame on the capture's sample clock. See [`sample_index`](Self::sample_index)
+    /// and [`captured_at`](Self::captured_at).
+    pub fn with_timing(mut self, sample_index: u64, captured_at: Instant) -> Self {
+        self.sample_index = sample_index;
+        self.captured_at = captured_at;
+        self
+    }
+
+    /// The samples of a single channel. Panics if `channel` is out of range.
+    pub fn channel(&self, channel: usize) -> &[T] {
+        let len = self.len();
+        &self.data[channel * len..(channel + 1) * len]
+    }
+
+    /// Mutable access to a single channel, for processing in place.
+    pub fn channel_mut(&mut self, channel: usize) -> &mut [T] {
+        let len = self.len();
+        &mut self.data[channel * len..(channel + 1) * len]
+    }
+
+    /// The first channel.
+    pub fn left(&self) -> &[T] {
+        self.channel(0)
+    }
+
+    /// The second channel, or the only channel of a mono frame (mono is dead center).
+    pub fn right(&self) -> &[T] {
+        self.channel(1.min(self.channels as usize - 1))
+    }
+
+    /// All samples, interleaved (`L R L R ...` for stereo).
+    pub fn interleaved(&self) -> impl Iterator<Item = T> + '_ {
+        let len = self.len();
+        (0..self.data.len()).map(move |i| {
+            let (index, channel) = (i / self.channels as usize, i % self.channels as usize);
+            self.data[channel * len + index]
+        })
+    }
+
+    /// All samples, laid out per channel.
+    pub fn planar(&self) -> &[T] {
+        &self.data
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Number of samples per channel.
+    pub fn len(&self) -> usize {
+        self.data.len() / self.channels as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// How much time this frame covers.
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.len() as f64 / self.sample_rate as f64)
+    }
+
+    /// Position of the first sample (per channel) since the capture started. This keeps counting
+    /// while frames are dropped, so a gap between consecutive frames means audio was lost.
+    pub fn sample_index(&self) -> u64 {
+        self.sample_index
+    }
+
+    /// [`sample_index`](Self::sample_index) in seconds: where this frame starts on the sample
+    /// clock.
+    pub fn start_time(&self) -> Duration {
+        Duration::from_secs_f64(self.sample_index as f64 / self.sample_rate as f64)
+    }
+
+    /// When the frame came off the source, i.e. when its last sample was captured.
+    pub fn captured_at(&self) -> Instant {
+        self.captured_at
+    }
+
+    /// How long ago the frame was captured.
+    pub fn age(&self) -> Duration {
+        self.captured_at.elapsed()
+    }
+}
+
+impl<T: Sample> Frame<T> {
+    /// Averages all channels into a single one.
+    pub fn downmix(&self) -> Frame<T> {
+        let len = self.len();
+        let channels = self.channels as usize;
+
+        let data = (0..len)
+            .map(|index| {
+                let sum: f32 = (0..channels).map(|channel| self.data[channel * len + index].to_f32()).sum();
+                T::from_f32(sum / channels as f32)
+            })
+            .collect();
+
+        Frame::from_planar(data, 1, self.sample_rate, len).with_timing(self.sample_index, self.captured_at)
+    }
+
+    /// Converts a stereo frame to mid (what both channels have in common) and side (how they
+    /// differ). Returns `None` for anything but stereo.
+    pub fn mid_side(&self) -> Option<MidSide> {
+        if self.channels != 2 {
+            return None;
+        }
+
+        let (mid, side) = self.left().iter()
+            .zip(self.right())
+            .map(|(left, right)| {
+                let (left, right) = (left.to_f32(), right.to_f32());
+                ((left + right) / 2.0, (left - right) / 2.0)
+            })
+            .unzip();
+
+        Some(MidSide { mid, side })
+    }
+}
+
+/// A stereo signal in mid/side form.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MidSide {
+    pub mid: Vec<f32>,
+    pub side: Vec<f32>,
+}
+
+impl MidSide {
+    /// Converts back to separate left and right channels.
+    pub fn to_left_right(&self) -> (Vec<f32>, Vec<f32>) {
+        self.mid.iter()
+            .zip(&self.side)
+            .map(|(mid, side)| (mid + side, mid - side))
+            .unzip()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels_and_stereo_helpers() {
+        let frame = Frame::new(&[0.5f32, -0.5, 1.0, 0.0, 0.0, 0.25], 2, 48000);
+
+        assert_eq!(frame.len(), 3);
+        assert_eq!(frame.left(), &[0.5, 1.0, 0.0]);
+        assert_eq!(frame.right(), &[-0.5, 0.0, 0.25]);
+        assert_eq!(frame.interleaved().collect::<Vec<_>>(), vec![0.5, -0.5, 1.0, 0.0, 0.0, 0.25]);
+
+        let mono = frame.downmix();
+        assert_eq!(mono.channels(), 1);
+        assert_eq!(mono.channel(0), &[0.0, 0.5, 0.125]);
+        assert_eq!(mono.left(), mono.right());
+
+        let mid_side = frame.mid_side().unwrap();
+        assert_eq!(mid_side.mid, vec![0.0, 0.5, 0.125]);
+        assert_eq!(mid_side.side, vec![0.5, 0.5, -0.125]);
+        assert_eq!(mid_side.to_left_right(), (frame.left().to_vec(), frame.right().to_vec()));
+        assert!(mono.mid_side().is_none());
+    }
+}
@@ -1,103 +1,438 @@
-use std::process::{Command, Stdio, Child};
-use std::io::Read;
-use std::sync::{Arc, atomic::{AtomicBool, Ordering}, mpsc::{sync_channel, Receiver, RecvError}};
+use std::path::Path;
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
+use std::sync::mpsc::{RecvTimeoutError, TryRecvError};
+use std::thread::JoinHandle;
+use std::time::Duration;
 use thiserror::Error;
 
+mod source;
+mod format;
+mod frame;
+mod stats;
+mod meter;
+mod agc;
+mod broadcast;
+mod devices;
+mod ffmpeg;
+mod wav;
+mod media;
+mod pcm;
+mod signal;
+mod supervisor;
+mod recorder;
+mod pool;
+mod reader;
+mod window;
+mod resample;
+mod shm;
+mod sync;
+#[cfg(test)]
+mod test_util;
+
+use stats::Counters;
+use broadcast::Broadcaster;
+use sync::lock;
+use reader::FrameReader;
+
+pub use source::{AudioSource, StopHandle};
+pub use format::{CaptureConfig, Sample, SampleFormat};
+pub use frame::{Frame, MidSide};
+pub use stats::CaptureStats;
+pub use agc::{Agc, AgcConfig};
+pub use meter::{to_dbfs, ChannelLevels, Levels, Meter, MeterConfig};
+pub use broadcast::{Backpressure, Subscription};
+pub use devices::{list_capture_devices, CaptureDevice, InputFormat};
+pub use ffmpeg::{FfmpegCommand, FfmpegSource};
+pub use wav::{Pacing, WavSource, WavWriter};
+pub use media::{MediaOptions, MediaSource};
+pub use pcm::PcmSource;
+pub use signal::{SignalSource, Waveform};
+pub use supervisor::{RestartPolicy, Supervised, SupervisorState};
+pub use recorder::Recorder;
+pub use window::{Windower, Windows};
+pub use resample::{ResampleQuality, Resampled, Resampler};
+pub use shm::{SharedRingPublisher, SharedRingReader, SharedRingWriter};
+
 #[derive(Error, Debug)]
 pub enum AudioCaptureError {
     #[error("Failed to start ffmpeg: {0}")]
     FfmpegError(#[from] std::io::Error),
 
     #[error("Stdout was already taken")]
-    NoStdout
+    NoStdout,
+
+    #[error("Failed to read audio: {0}")]
+    ReadError(std::io::Error),
+
+    #[error("Invalid WAV file: {0}")]
+    InvalidWav(String),
+
+    #[error("Failed to write recording: {0}")]
+    RecordError(std::io::Error),
+
+    #[error("Invalid capture config: {0}")]
+    InvalidConfig(String),
+
+    #[error("Capture device {0} does not exist")]
+    DeviceNotFound(String),
+
+    #[error("Failed to open capture device {device}: {stderr}")]
+    DeviceOpen {
+        device: String,
+        stderr: String
+    },
+
+    #[error("Failed to decode {path}: {stderr}")]
+    MediaOpen {
+        path: String,
+        stderr: String
+    },
+
+    #[error("ffmpeg exited with {status}: {stderr}")]
+    ProcessExited {
+        status: ExitStatus,
+        stderr: String
+    },
+
+    #[error("Shared memory ring failed: {0}")]
+    SharedMemory(std::io::Error),
+
+    #[error("The audio source was stopped")]
+    Stopped,
+
+    #[error("The audio source ran out of audio")]
+    EndOfStream
+}
+
+impl AudioCaptureError {
+    /// A copy to hand out more than once. I/O errors keep their kind and message, but not their
+    /// source.
+    fn duplicate(&self) -> Self {
+        use AudioCaptureError::*;
+        let io = |e: &std::io::Error| std::io::Error::new(e.kind(), e.to_string());
+        match self {
+            FfmpegError(e) => FfmpegError(io(e)),
+            NoStdout => NoStdout,
+            ReadError(e) => ReadError(io(e)),
+            InvalidWav(message) => InvalidWav(message.clone()),
+            RecordError(e) => RecordError(io(e)),
+            InvalidConfig(message) => InvalidConfig(message.clone()),
+            DeviceNotFound(device) => DeviceNotFound(device.clone()),
+            DeviceOpen { device, stderr } => DeviceOpen { device: device.clone(), stderr: stderr.clone() },
+            MediaOpen { path, stderr } => MediaOpen { path: path.clone(), stderr: stderr.clone() },
+            ProcessExited { status, stderr } => ProcessExited { status: *status, stderr: stderr.clone() },
+            SharedMemory(e) => SharedMemory(io(e)),
+            Stopped => Stopped,
+            EndOfStream => EndOfStream,
+        }
+    }
 }
 
-pub struct AudioCapturer {
-    ffmpeg: Child,
+/// Captures audio from a source on a thread of its own, and hands it out as [`Frame`]s.
+///
+/// Dropping it stops capturing like [`stop`](Self::stop) does, but doesn't wait for a source
+/// that can't interrupt a read in progress (see [`AudioSource::can_interrupt`]). Its thread,
+/// and the source with it, stay behind until that read returns, which may be never. Call `stop`
+/// to wait for them instead.
+pub struct AudioCapturer<T: Sample = i16> {
+    stop_handle: StopHandle,
     do_read: Arc<AtomicBool>,
-    receiver: Receiver<Vec<[u8; 2]>>
+    config: CaptureConfig,
+    counters: Arc<Counters>,
+    broadcaster: Arc<Broadcaster<Frame<T>>>,
+    frames: Subscription<Frame<T>>,
+    latest: Subscription<Frame<T>>,
+    meter: Arc<Mutex<Meter>>,
+    agc: Arc<Mutex<Option<Agc>>>,
+    error: Arc<Mutex<Option<AudioCaptureError>>>,
+    /// Whether read_frame and the like have returned the error yet.
+    error_reported: AtomicBool,
+    thread: Option<JoinHandle<()>>,
+    /// Whether stopping the source gets the reader thread out of a read, see
+    /// [`AudioSource::can_interrupt`].
+    can_interrupt: bool
 }
 
-impl AudioCapturer {
-    pub fn new(buffer_size: usize) -> Result<Self, AudioCaptureError> {
+impl<T: Sample> AudioCapturer<T> {
+    /// Records from `device` through ffmpeg. `CaptureDevice::default()` is ALSA's `default`
+    /// device (desktop audio), [`list_capture_devices`] lists the others.
+    pub fn new(device: &CaptureDevice, config: CaptureConfig, buffer_size: usize) -> Result<Self, AudioCaptureError> {
+        Self::with_source(FfmpegSource::new(device, config)?, buffer_size)
+    }
+
+    /// Captures audio from any [`AudioSource`], in frames of `buffer_size` samples per channel.
+    pub fn with_source<S: AudioSource + 'static>(source: S, buffer_size: usize) -> Result<Self, AudioCaptureError> {
+        let config = source.config();
+        if config.channels == 0 || config.sample_rate == 0 {
+            return Err(AudioCaptureError::InvalidConfig(format!("{:?}", config)));
+        }
+
         let do_read = Arc::new(AtomicBool::new(true));
+        let counters = Arc::new(Counters::default());
+        let meter = Arc::new(Mutex::new(Meter::default()));
+        let agc = Arc::new(Mutex::new(None::<Agc>));
+        let error = Arc::new(Mutex::new(None));
+        let stop_handle = source.stop_handle();
+        let can_interrupt = source.can_interrupt();
+        let broadcaster = Arc::new(Broadcaster::new());
+
+        // The subscription behind read_frame. If there is no one to recieve a buffer from a
+        // realtime source, disregard it: better to skip some frames than to build up a backlog.
+        // Other sources can simply wait for us, once we start reading, so they don't hold up
+        // the other consumers of a capturer that never calls read_frame.
+        let realtime = source.is_realtime();
+        let frames = if realtime {
+            broadcaster.subscribe(1, Backpressure::DropNewest)
+        } else {
+            broadcaster.subscribe_standby(1, Backpressure::Block)
+        };
+        // The one behind latest_frame only ever holds the newest frame.
+        let latest = broadcaster.subscribe_standby(1, Backpressure::DropOldest);
 
-        let mut ffmpeg = Command::new("ffmpeg")
-            .arg("-f").arg("alsa") // Input format
-            .arg("-i").arg("default") // Recording device (desktop audio)
-            .arg("-f").arg("s16le") // Output format (unsigned 32-bit little-endian ints)
-            .arg("-v").arg("quiet")
-            .arg("pipe:1") // Pipe PCM audio to stdout
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null()) // Ignore stderr, so it doesn't inherit the stream from the
-                                   // program.
-            .spawn()?;
-
-        // Set a bound on the channel to prevent memory leaks
-        let (sender, receiver) = sync_channel::<Vec<[u8; 2]>>(1);
-
-        // This reader thread is responsible for reading ffmpeg's output (PCM data from ALSA),
-        // parsing it and calling the listeners.
-        {
-            let mut stdout = ffmpeg.stdout.take().ok_or(AudioCaptureError::NoStdout)?;
+        // This reader thread is responsible for reading the source's output (PCM data),
+        // parsing it and sending it to the subscribers.
+        let thread = {
+            let mut source = source;
             let do_read = Arc::clone(&do_read);
+            let counters = Arc::clone(&counters);
+            let meter = Arc::clone(&meter);
+            let agc = Arc::clone(&agc);
+            let broadcaster = Arc::clone(&broadcaster);
+            let error = Arc::clone(&error);
 
             std::thread::spawn(move || {
+                let mut reader = FrameReader::<T>::new(config, buffer_size);
+
                 loop {
-                    let mut raw_buf: Vec<u8> = vec![0; buffer_size*2];
-                    if let Err(..) = stdout.read_exact(&mut raw_buf) {
-                        // Exit if reading the stream failed
-                        return;
+                    // A source that isn't realtime waits for someone to take its audio, instead
+                    // of running out before anyone subscribed.
+                    if !realtime && !broadcaster.wait_for_subscriber() {
+                        break;
                     }
 
-                    // Every 4 bytes should be converted to a u32. The length of this
-                    // buffer should be buffer_size.
-                    let parsed_buf: Vec<[u8; 2]> = raw_buf
-                        .iter()
-                        .step_by(2)
-                        .enumerate()
-                        .map(|(i, _)| {
-                            // The unwrap is safe because read_exact guarantees that raw_buf will
-                            // be completely filled and the length of raw_buf is always divisible
-                            // by 4.
-                            let bytes: [u8; 2] = raw_buf[(i*2)..(i*2)+2].try_into().unwrap();
-                            bytes
-                        })
-                        .collect();
-                    
-                    // If there is no one to recieve this buffer, disregard it. Better to skip
-                    // some frames than to build up a backlog
-                    let _ = sender.try_send(parsed_buf);
+                    let mut frame = match reader.read(&mut source) {
+                        Ok(frame) => frame,
+                        Err(e) => {
+                            // Exit if reading the stream failed, and keep the reason for
+                            // read_frame. Failing because we were told to stop is not an error.
+                            let e = if do_read.load(Ordering::SeqCst) { e } else { AudioCaptureError::Stopped };
+                            *lock(&error) = Some(e);
+                            break;
+                        }
+                    };
+                    counters.produced();
+                    // Meter what came in, before the AGC evens it out.
+                    lock(&meter).update(&frame);
+                    if let Some(agc) = lock(&agc).as_mut() {
+                        agc.process(&mut frame);
+                    }
+
+                    broadcaster.send(frame);
 
                     if !do_read.load(Ordering::SeqCst) {
                         // Stop reading
                         break;
                     }
                 }
-            });
-        }
+
+                broadcaster.close();
+            })
+        };
 
         Ok(AudioCapturer {
-            ffmpeg,
+            stop_handle,
             do_read,
-            receiver
+            config,
+            counters,
+            broadcaster,
+            frames,
+            latest,
+            meter,
+            agc,
+            error,
+            error_reported: AtomicBool::new(false),
+            thread: Some(thread),
+            can_interrupt
         })
     }
 
-    pub fn read_frame(&self) -> Result<Vec<[u8; 2]>, RecvError> {
-        self.receiver.recv()
+    /// The rate, channel count and wire format of the audio being captured.
+    pub fn config(&self) -> CaptureConfig {
+        self.config
+    }
+
+    /// How many frames have been captured and dropped so far.
+    pub fn stats(&self) -> CaptureStats {
+        self.counters.snapshot(self.frames.dropped())
     }
 
-    pub fn stop(&mut self) {
-        let _ = self.ffmpeg.kill();
-        self.do_read.store(false, Ordering::Relaxed);
+    /// Levels of the most recently captured frame, whether or not anyone read it.
+    pub fn levels(&self) -> Levels {
+        lock(&self.meter).levels().clone()
+    }
+
+    /// Changes how peaks are held and what counts as clipping, see [`MeterConfig`].
+    pub fn set_meter_config(&self, config: MeterConfig) {
+        lock(&self.meter).set_config(config);
+    }
+
+    /// Turns automatic gain control on (or off with `None`) for every consumer of this capture.
+    /// [`levels`](Self::levels) keeps metering the audio as it comes in.
+    pub fn set_agc(&self, config: Option<AgcConfig>) {
+        *lock(&self.agc) = config.map(Agc::new);
+    }
+
+    /// Blocks until the next frame is available. Once capturing has ended, the first call after
+    /// the last frame returns why (like [`AudioCaptureError::ProcessExited`]) and every call after
+    /// that returns [`AudioCaptureError::Stopped`].
+    pub fn read_frame(&self) -> Result<Frame<T>, AudioCaptureError> {
+        self.frames.recv().map_err(|_| self.end_reason())
+    }
+
+    /// Like [`read_frame`](Self::read_frame), but returns `None` right away if no frame is ready.
+    pub fn try_read_frame(&self) -> Result<Option<Frame<T>>, AudioCaptureError> {
+        match self.frames.try_recv() {
+            Ok(frame) => Ok(Some(frame)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(self.end_reason()),
+        }
+    }
+
+    /// Like [`read_frame`](Self::read_frame), but returns `None` if no frame comes in within
+    /// `timeout`, say because the device went quiet or ffmpeg hangs.
+    pub fn read_frame_timeout(&self, timeout: Duration) -> Result<Option<Frame<T>>, AudioCaptureError> {
+        match self.frames.recv_timeout(timeout) {
+            Ok(frame) => Ok(Some(frame)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(self.end_reason()),
+        }
+    }
+
+    /// The newest frame captured since the last call, or `None` if nothing new came in. Never
+    /// blocks, so a render loop can call it once per display frame. Frames in between are skipped
+    /// without counting as dropped.
+    ///
+    /// This is a separate stream from [`read_frame`](Self::read_frame)'s, use one or the other.
+    pub fn latest_frame(&self) -> Result<Option<Frame<T>>, AudioCaptureError> {
+        let mut latest = None;
+        loop {
+            match self.latest.try_recv() {
+                Ok(frame) => latest = Some(frame),
+                Err(TryRecvError::Empty) => return Ok(latest),
+                // Hand out what's left first, the reason can wait for the next call.
+                Err(TryRecvError::Disconnected) if latest.is_some() => return Ok(latest),
+                Err(TryRecvError::Disconnected) => return Err(self.end_reason()),
+            }
+        }
+    }
+
+    /// Why capturing ended, for the first reader to ask.
+    fn end_reason(&self) -> AudioCaptureError {
+        if self.error_reported.swap(true, Ordering::SeqCst) {
+            return AudioCaptureError::Stopped;
+        }
+        self.final_error()
+    }
+
+    /// Why capturing ended, however often it was asked before.
+    fn final_error(&self) -> AudioCaptureError {
+        lock(&self.error)
+            .as_ref()
+            .map_or(AudioCaptureError::Stopped, AudioCaptureError::duplicate)
+    }
+
+    /// Starts an independent stream of every frame captured from now on, so several consumers
+    /// (say a visualizer and a recorder) can share one source. Each subscription queues up to
+    /// `capacity` frames and applies its own `policy` when that fills up.
+    pub fn subscribe(&self, capacity: usize, policy: Backpressure) -> Subscription<Frame<T>> {
+        self.broadcaster.subscribe(capacity, policy)
+    }
+
+    /// Overlapping windows of `window` samples per channel, one every `hop` samples, of
+    /// everything captured from now on. Up to `capacity` frames are queued for the windows; when
+    /// the consumer falls further behind, the oldest are dropped and windowing starts over after
+    /// the gap.
+    pub fn windows(&self, window: usize, hop: usize, capacity: usize) -> Result<Windows<T>, AudioCaptureError> {
+        let windower = Windower::new(window, hop)?;
+        Ok(Windows::new(self.subscribe(capacity, Backpressure::DropOldest), windower))
+    }
+
+    /// Everything captured from now on, converted to `target_rate`. Up to `capacity` frames are
+    /// queued for resampling; when the consumer falls further behind, the oldest are dropped and
+    /// resampling starts over after the gap.
+    pub fn resampled(&self, target_rate: u32, quality: ResampleQuality, capacity: usize) -> Result<Resampled<T>, AudioCaptureError> {
+        let resampler = Resampler::new(target_rate, quality)?;
+        Ok(Resampled::new(self.subscribe(capacity, Backpressure::DropOldest), resampler))
+    }
+
+    /// Tees everything captured from now on into a WAV file at `path`, in the capturer's sample
+    /// rate and channel layout and `T`'s sample format. With `rotate_every`, a new file is started
+    /// whenever the current one holds that much audio, numbered like `path-0000.wav`.
+    ///
+    /// The recorder never drops audio: if the disk can't keep up, capturing slows down instead.
+    pub fn record(&self, path: impl AsRef<Path>, rotate_every: Option<Duration>) -> Result<Recorder, AudioCaptureError> {
+        let config = CaptureConfig { format: T::FORMAT, ..self.config };
+        let frames = self.subscribe(64, Backpressure::Block);
+        Recorder::start(frames, path.as_ref(), config, rotate_every)
+    }
+
+    /// Publishes everything captured from now on to the shared memory ring `name`, holding the
+    /// last `capacity` sample frames, so other processes can read along with a
+    /// [`SharedRingReader`] instead of each starting their own capture.
+    pub fn share(&self, name: &str, capacity: usize) -> Result<SharedRingPublisher, AudioCaptureError> {
+        let writer = SharedRingWriter::create(name, self.config, capacity)?;
+        // Writing to the ring never waits for readers, so this queue hardly fills up.
+        let frames = self.subscribe(16, Backpressure::DropOldest);
+        Ok(SharedRingPublisher::start(frames, writer))
+    }
+
+    /// Stops the source and waits for the reader thread to finish, which also reaps the ffmpeg
+    /// process if there is one. Frames that were already queued can still be read.
+    ///
+    /// Returns `Ok` if capturing ended cleanly: because of this call, or because the source ran
+    /// out of audio. Otherwise returns the error that ended it, even if
+    /// [`read_frame`](Self::read_frame) or the like already reported it. Calling this again does
+    /// nothing and returns `Ok`.
+    ///
+    /// A source that can't interrupt a read in progress (like a [`PcmSource`] waiting on a
+    /// quiet socket, unless it is [`interruptible`](PcmSource::interruptible)) holds this up until
+    /// that read returns. Dropping the capturer doesn't wait for such a read.
+    pub fn stop(&mut self) -> Result<(), AudioCaptureError> {
+        let Some(thread) = self.signal_stop() else {
+            return Ok(());
+        };
+
+        if thread.join().is_err() {
+            return Err(AudioCaptureError::ReadError(std::io::Error::other("reader thread panicked")));
+        }
+        match self.final_error() {
+            AudioCaptureError::Stopped | AudioCaptureError::EndOfStream => Ok(()),
+            error => Err(error),
+        }
+    }
+
+    /// Tells the source and the reader thread to stop, and hands over the thread to wait for.
+    fn signal_stop(&mut self) -> Option<JoinHandle<()>> {
+        let thread = self.thread.take()?;
+
+        self.do_read.store(false, Ordering::SeqCst);
+        self.stop_handle.stop();
+        // Unblocks the reader thread if it is waiting for a Block subscriber to catch up.
+        self.broadcaster.close();
+
+        Some(thread)
     }
 }
 
-impl Drop for AudioCapturer {
+impl<T: Sample> Drop for AudioCapturer<T> {
     fn drop(&mut self) {
-        self.do_read.store(false, Ordering::Relaxed);
-        self.stop(); 
+        if self.can_interrupt {
+            let _ = self.stop();
+        } else {
+            // The reader thread finishes by itself once the pending read returns.
+            self.signal_stop();
+        }
     }
 }
 
@@ -107,21 +442,362 @@ mod tests {
     use std::time::Instant;
 
     #[test]
+    #[ignore = "needs ffmpeg and a live ALSA device"]
     fn listen_to_audio_5_sec() {
-        let mut capturer = AudioCapturer::new(2048).unwrap();
+        let mut capturer = AudioCapturer::<i16>::new(&CaptureDevice::default(), CaptureConfig::default(), 2048).unwrap();
 
         let start = Instant::now();
 
         while Instant::now().duration_since(start).as_millis() < 5000 {
-            let frame: Vec<i16> = capturer.read_frame().unwrap().into_iter()
-                .map(|bytes| i16::from_le_bytes(bytes))
-                .collect();
+            let frame: Vec<i16> = capturer.read_frame().unwrap().interleaved().collect();
             //let f32_frame: Vec<f32> = frame.iter()
             //    .map(|amplitude| (*amplitude as f32) / (u32::max_value() as f32))
             //    .collect();
             println!("{:?}", frame);
         }
 
-        capturer.stop();
+        capturer.stop().unwrap();
+    }
+
+    #[test]
+    fn capture_from_signal_source() {
+        let config = CaptureConfig { sample_rate: 44100, channels: 1, format: SampleFormat::S16 };
+        let source = SignalSource::new(Waveform::Square { frequency: 441.0 }, 0.5, config);
+        let mut capturer = AudioCapturer::<i16>::with_source(source, 100).unwrap();
+
+        let frame = capturer.read_frame().unwrap();
+
+        assert_eq!(frame.len(), 100);
+        assert_eq!(frame.sample_rate(), 44100);
+        // One full period of the square wave: 50 samples high, 50 samples low.
+        assert!(frame.channel(0)[..50].iter().all(|&s| s == 16384));
+        assert!(frame.channel(0)[50..].iter().all(|&s| s == -16384));
+
+        // Every frame of a square wave meters the same.
+        let levels = capturer.levels();
+        assert_eq!(levels.channels.len(), 1);
+        assert_eq!(levels.channels[0].rms, 0.5);
+        assert_eq!(levels.channels[0].peak, 0.5);
+        assert!(!levels.clipped());
+
+        capturer.stop().unwrap();
+    }
+
+    #[test]
+    fn play_back_test_wav() {
+        let source = WavSource::open("test.wav", Pacing::Unthrottled).unwrap();
+        assert_eq!(source.config(), CaptureConfig { sample_rate: 48000, channels: 2, format: SampleFormat::S16 });
+
+        let capturer = AudioCapturer::<f32>::with_source(source, 2048).unwrap();
+
+        // Unthrottled playback doesn't drop anything, so every full frame in the file comes out.
+        let mut frames = 0;
+        while let Ok(frame) = capturer.read_frame() {
+            assert_eq!(frame.len(), 2048);
+            assert_eq!(frame.channels(), 2);
+            assert!(frame.planar().iter().all(|s| (-1.0..=1.0).contains(s)));
+            frames += 1;
+        }
+
+        let mut file = std::io::BufReader::new(std::fs::File::open("test.wav").unwrap());
+        let (_, data_len) = wav::read_header(&mut file).unwrap();
+        assert_eq!(frames, data_len / (2048 * 4));
+    }
+
+    #[test]
+    fn count_dropped_frames() {
+        let config = CaptureConfig { sample_rate: 48000, channels: 2, format: SampleFormat::F32 };
+        let source = SignalSource::new(Waveform::WhiteNoise, 1.0, config);
+        let capturer = AudioCapturer::<f32>::with_source(source, 480).unwrap();
+
+        // Fall behind by a few frames' worth of audio. The frame right after that is the one that
+        // was waiting in the channel, the one after that comes after the gap.
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let first = capturer.read_frame().unwrap();
+        let second = capturer.read_frame().unwrap();
+
+        assert!(second.sample_index() > first.sample_index() + 480);
+        assert!(second.captured_at() > first.captured_at());
+
+        let stats = capturer.stats();
+        let skipped = (second.sample_index() - first.sample_index()) / 480 - 1;
+        assert!(stats.dropped >= skipped);
+        assert!(stats.produced >= stats.dropped + 2);
+    }
+
+    #[test]
+    fn subscribers_share_one_source() {
+        let source = WavSource::open("test.wav", Pacing::Unthrottled).unwrap();
+        let capturer = AudioCapturer::<i16>::with_source(source, 1024).unwrap();
+        let recorder = capturer.subscribe(4, Backpressure::Block);
+        let visualizer = capturer.subscribe(1, Backpressure::DropOldest);
+
+        let mut recorded = 0;
+        let mut last_index = None;
+        while let Ok(frame) = recorder.recv() {
+            // A blocking subscriber doesn't miss a thing.
+            if let Some(last_index) = last_index {
+                assert_eq!(frame.sample_index(), last_index + 1024);
+            }
+            last_index = Some(frame.sample_index());
+            recorded += 1;
+
+            let _ = capturer.read_frame();
+        }
+
+        // The visualizer never read anything, so it only holds on to the very last frame.
+        assert!(recorded > 1);
+        assert!(visualizer.dropped() >= recorded - 2);
+        assert_eq!(Some(visualizer.recv().unwrap().sample_index()), last_index);
+        assert!(visualizer.recv().is_err());
+    }
+
+    #[test]
+    fn report_why_capturing_ended() {
+        let source = WavSource::open("test.wav", Pacing::Unthrottled).unwrap();
+        let capturer = AudioCapturer::<i16>::with_source(source, 1 << 20).unwrap();
+
+        assert!(matches!(capturer.read_frame(), Err(AudioCaptureError::EndOfStream)));
+        assert!(matches!(capturer.read_frame(), Err(AudioCaptureError::Stopped)));
+    }
+
+    #[test]
+    fn stop_a_waiting_reader() {
+        // Unthrottled playback waits for read_frame, which is called once and then no more.
+        let source = WavSource::open("test.wav", Pacing::Unthrottled).unwrap();
+        let mut capturer = AudioCapturer::<i16>::with_source(source, 256).unwrap();
+        let _ = capturer.try_read_frame().unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+
+        capturer.stop().unwrap();
+        // The queued frame can still be read, the one the reader was holding is gone.
+        assert!(capturer.read_frame().is_ok());
+        assert!(matches!(capturer.read_frame(), Err(AudioCaptureError::Stopped)));
+        assert!(capturer.stats().produced <= 3);
+    }
+
+    #[test]
+    fn consume_a_file_without_read_frame() {
+        let path = test_util::temp_path("consume.wav");
+
+        // Every consumer keeps an unthrottled file going by itself...
+        let source = WavSource::open("test.wav", Pacing::Unthrottled).unwrap();
+        let capturer = AudioCapturer::<i16>::with_source(source, 4800).unwrap();
+        let recorder = capturer.record(&path, None).unwrap();
+        let windows = capturer.windows(4096, 512, 1024).unwrap();
+        assert!(windows.count() > 0);
+        let paths = recorder.stop().unwrap();
+
+        // ...and the blocking recorder gets all of it.
+        let mut recorded = WavSource::open(&paths[0], Pacing::Unthrottled).unwrap();
+        let mut buf = vec![0u8; 4800 * 4];
+        let mut frames = 0;
+        while recorded.read_exact(&mut buf).is_ok() {
+            frames += 1;
+        }
+        assert!(frames > 1);
+        assert_eq!(frames, capturer.stats().produced);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn poll_for_frames() {
+        // A stream that stays silent until the other end hangs up.
+        let config = CaptureConfig { sample_rate: 8000, channels: 1, format: SampleFormat::S16 };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let quiet = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let capturer = AudioCapturer::<i16>::with_source(PcmSource::new(listener.accept().unwrap().0, config), 80).unwrap();
+
+        assert!(capturer.try_read_frame().unwrap().is_none());
+        assert!(capturer.read_frame_timeout(Duration::from_millis(20)).unwrap().is_none());
+        assert!(capturer.latest_frame().unwrap().is_none());
+        drop(quiet);
+        assert!(matches!(capturer.read_frame_timeout(Duration::from_secs(5)), Err(AudioCaptureError::EndOfStream)));
+
+        // latest_frame skips ahead to the newest frame.
+        let source = SignalSource::new(Waveform::Silence, 0.0, config);
+        let capturer = AudioCapturer::<i16>::with_source(source, 80).unwrap();
+        let first = capturer.read_frame_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        let latest = capturer.latest_frame().unwrap().unwrap();
+        assert!(latest.sample_index() >= first.sample_index() + 5 * 80);
+        assert!(capturer.latest_frame().unwrap().is_none_or(|next| next.sample_index() > latest.sample_index()));
+    }
+
+    #[test]
+    fn supervisor_restarts_failed_sources() {
+        let config = CaptureConfig { sample_rate: 8000, channels: 1, format: SampleFormat::S16 };
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let policy = RestartPolicy {
+            initial_backoff: std::time::Duration::from_millis(1),
+            max_backoff: std::time::Duration::from_millis(2),
+            max_restarts: Some(2),
+        };
+
+        // Every source dies after one frame. The factory stops working after three sources.
+        let mut started = 0;
+        let source = Supervised::new(
+            move || {
+                started += 1;
+                if started > 3 {
+                    return Err(AudioCaptureError::DeviceOpen { device: "test".into(), stderr: String::new() });
+                }
+                let source = SignalSource::new(Waveform::Silence, 0.0, config);
+                let stop = source.stop_handle();
+                Ok(StopAfter { source, stop, frames: 1 })
+            },
+            policy,
+            {
+                let states = Arc::clone(&states);
+                move |state| states.lock().unwrap().push(state)
+            },
+        ).unwrap();
+        let capturer = AudioCapturer::<i16>::with_source(source, 80).unwrap();
+
+        for _ in 0..3 {
+            capturer.read_frame().unwrap();
+        }
+        assert!(matches!(capturer.read_frame(), Err(AudioCaptureError::DeviceOpen { .. })));
+
+        let states = states.lock().unwrap();
+        assert_eq!(states[0], SupervisorState::Running { restarts: 0 });
+        assert_eq!(states[3], SupervisorState::Running { restarts: 1 });
+        assert_eq!(states.last(), Some(&SupervisorState::GaveUp));
+        assert_eq!(states.iter().filter(|s| matches!(s, SupervisorState::Restarting { .. })).count(), 4);
+    }
+
+    /// Fails with an error after handing out a number of frames.
+    struct StopAfter<S> {
+        source: S,
+        stop: StopHandle,
+        frames: usize,
+    }
+
+    impl<S: AudioSource> AudioSource for StopAfter<S> {
+        fn config(&self) -> CaptureConfig {
+            self.source.config()
+        }
+
+        fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), AudioCaptureError> {
+            if self.frames == 0 {
+                return Err(AudioCaptureError::EndOfStream);
+            }
+            self.frames -= 1;
+            self.source.read_exact(buf)
+        }
+
+        fn stop_handle(&self) -> StopHandle {
+            self.stop.clone()
+        }
+    }
+
+    #[test]
+    fn even_out_levels() {
+        let config = CaptureConfig { sample_rate: 48000, channels: 2, format: SampleFormat::F32 };
+        let source = SignalSource::new(Waveform::Sine { frequency: 1000.0 }, 0.01, config);
+        let capturer = AudioCapturer::<f32>::with_source(source, 480).unwrap();
+        capturer.set_agc(Some(AgcConfig::default()));
+
+        let mut frame = capturer.read_frame().unwrap();
+        for _ in 0..10 {
+            frame = capturer.read_frame().unwrap();
+        }
+        let rms = (frame.left().iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt();
+        assert!((to_dbfs(rms) - AgcConfig::default().target_db).abs() < 1.0);
+        // The meter still sees the quiet input.
+        assert!(capturer.levels().channels[0].rms_db() < -40.0);
+    }
+
+    #[test]
+    fn overlapping_analysis_windows() {
+        let config = CaptureConfig { sample_rate: 48000, channels: 1, format: SampleFormat::F32 };
+        let source = SignalSource::new(Waveform::Sine { frequency: 1000.0 }, 1.0, config);
+        let capturer = AudioCapturer::<f32>::with_source(source, 480).unwrap();
+
+        let windows: Vec<Frame<f32>> = capturer.windows(4096, 512, 64).unwrap().take(4).collect();
+        for pair in windows.windows(2) {
+            assert_eq!(pair[1].sample_index(), pair[0].sample_index() + 512);
+            // Overlapping windows share audio.
+            assert_eq!(pair[1].channel(0)[..4096 - 512], pair[0].channel(0)[512..]);
+        }
+        assert!(windows.iter().all(|window| window.len() == 4096));
+    }
+
+    #[test]
+    fn resample_a_capture() {
+        let config = CaptureConfig { sample_rate: 48000, channels: 2, format: SampleFormat::F32 };
+        let source = SignalSource::new(Waveform::Sine { frequency: 1000.0 }, 1.0, config);
+        let capturer = AudioCapturer::<f32>::with_source(source, 480).unwrap();
+
+        let frames: Vec<Frame<f32>> = capturer.resampled(16000, ResampleQuality::WindowedSinc, 64).unwrap().take(5).collect();
+        for pair in frames.windows(2) {
+            assert_eq!(pair[1].sample_index(), pair[0].sample_index() + pair[0].len() as u64);
+        }
+        assert!(frames.iter().all(|frame| frame.sample_rate() == 16000 && frame.channels() == 2));
+        // A third as many samples come out as go in, give or take the sinc's lag.
+        assert_eq!(frames[4].len(), 160);
+    }
+
+    #[test]
+    fn share_with_other_processes() {
+        let name = format!("test_{}_share", std::process::id());
+        let config = CaptureConfig { sample_rate: 48000, channels: 2, format: SampleFormat::S16 };
+        let source = SignalSource::new(Waveform::Square { frequency: 480.0 }, 0.5, config);
+        let capturer = AudioCapturer::<i16>::with_source(source, 480).unwrap();
+        let publisher = capturer.share(&name, 4800).unwrap();
+
+        let mut reader = SharedRingReader::attach(&name).unwrap();
+        assert_eq!(reader.config().sample_rate, 48000);
+        let frame = reader.read_timeout::<i16>(100, Duration::from_secs(5)).unwrap().unwrap();
+        assert!(frame.interleaved().all(|sample| sample == 16384 || sample == -16384));
+
+        publisher.stop().unwrap();
+        assert!(reader.is_closed());
+    }
+
+    #[test]
+    fn record_what_was_captured() {
+        let dir = test_util::temp_path("record");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Feed test.wav through a socket, so nothing is captured before both read_frame and the
+        // recorder are listening.
+        let mut wav = WavSource::open("test.wav", Pacing::Unthrottled).unwrap();
+        let (mut writer, reader) = std::os::unix::net::UnixStream::pair().unwrap();
+        let source = PcmSource::new(reader, wav.config()).paced(Pacing::Unthrottled);
+        let capturer = AudioCapturer::<i16>::with_source(source, 4800).unwrap();
+        assert!(capturer.try_read_frame().unwrap().is_none());
+        let recorder = capturer.record(dir.join("take.wav"), Some(std::time::Duration::from_secs(1))).unwrap();
+        let sender = std::thread::spawn(move || {
+            let mut buf = vec![0u8; 4800 * 4];
+            while wav.read_exact(&mut buf).is_ok() {
+                std::io::Write::write_all(&mut writer, &buf).unwrap();
+            }
+        });
+
+        let mut captured = Vec::new();
+        while let Ok(frame) = capturer.read_frame() {
+            captured.extend(frame.interleaved());
+        }
+        sender.join().unwrap();
+        let paths = recorder.stop().unwrap();
+
+        // 4800 sample frames are 0.1 seconds, so the files are rotated every 10 frames.
+        assert!(paths.len() > 1);
+        assert_eq!(paths[1], dir.join("take-0001.wav"));
+
+        let mut recorded = Vec::new();
+        for path in &paths {
+            let mut source = WavSource::open(path, Pacing::Unthrottled).unwrap();
+            assert_eq!(source.config(), capturer.config());
+            let mut buf = vec![0u8; 4800 * 4];
+            while source.read_exact(&mut buf).is_ok() {
+                recorded.extend(buf.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]])));
+            }
+        }
+        assert_eq!(recorded, captured);
+
+        let _ = std::fs::remove_dir_all(dir);
     }
 }
@@ -0,0 +1,255 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
+use std::time::Duration;
+use crate::ffmpeg::FfmpegSource;
+use crate::format::CaptureConfig;
+use crate::source::{AudioSource, Pacer, StopHandle};
+use crate::wav::Pacing;
+use crate::sync::lock;
+use crate::AudioCaptureError;
+
+/// How a [`MediaSource`] plays its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MediaOptions {
+    /// Where to start playing, and where every loop starts again.
+    pub start: Duration,
+    /// Start over at `start` when the file ends, instead of ending the stream.
+    pub looping: bool,
+    pub pacing: Pacing,
+}
+
+impl Default for MediaOptions {
+    fn default() -> Self {
+        MediaOptions {
+            start: Duration::ZERO,
+            looping: false,
+            pacing: Pacing::RealTime,
+        }
+    }
+}
+
+/// Plays back anything ffmpeg can decode (mp3, flac, ogg, the audio track of an mp4, ...),
+/// converted to the requested config. The end of the file shows up as
+/// [`AudioCaptureError::EndOfStream`], a file ffmpeg can't decode as
+/// [`AudioCaptureError::MediaOpen`].
+pub struct MediaSource {
+    program: OsString,
+    path: PathBuf,
+    config: CaptureConfig,
+    options: MediaOptions,
+    ffmpeg: FfmpegSource,
+    current_stop: Arc<Mutex<StopHandle>>,
+    stopped: Arc<AtomicBool>,
+    pacer: Option<Pacer>,
+    /// Whether the current pass through the file has produced anything yet.
+    received_audio: bool,
+}
+
+impl MediaSource {
+    pub fn open(path: impl AsRef<Path>, config: CaptureConfig, options: MediaOptions) -> Result<Self, AudioCaptureError> {
+        Self::with_program("ffmpeg", path, config, options)
+    }
+
+    /// Like [`open`](Self::open), but runs `program` instead of the `ffmpeg` on the `PATH`.
+    pub fn with_program(
+        program: impl Into<OsString>,
+        path: impl AsRef<Path>,
+        config: CaptureConfig,
+        options: MediaOptions,
+    ) -> Result<Self, AudioCaptureError> {
+        let path = path.as_ref().to_owned();
+        // ffmpeg would report this too, but not as clearly.
+        std::fs::metadata(&path).map_err(AudioCaptureError::ReadError)?;
+
+        let program = program.into();
+        let ffmpeg = spawn(&program, &path, config, options)?;
+
+        Ok(MediaSource {
+            program,
+            path,
+            config,
+            options,
+            current_stop: Arc::new(Mutex::new(ffmpeg.stop_handle())),
+            ffmpeg,
+            stopped: Arc::new(AtomicBool::new(false)),
+            pacer: (options.pacing == Pacing::RealTime).then(|| Pacer::new(config.sample_rate)),
+            received_audio: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Starts the next loop with a fresh ffmpeg.
+    fn restart(&mut self) -> Result<(), AudioCaptureError> {
+        let ffmpeg = spawn(&self.program, &self.path, self.config, self.options)?;
+        *lock(&self.current_stop) = ffmpeg.stop_handle();
+        self.ffmpeg = ffmpeg;
+        self.received_audio = false;
+
+        // stop() may have gone to the old ffmpeg while this one was starting.
+        if self.stopped.load(Ordering::SeqCst) {
+            self.ffmpeg.stop_handle().stop();
+        }
+        Ok(())
+    }
+}
+
+impl AudioSource for MediaSource {
+    fn config(&self) -> CaptureConfig {
+        self.config
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), AudioCaptureError> {
+        loop {
+            if self.stopped.load(Ordering::SeqCst) {
+                return Err(AudioCaptureError::Stopped);
+            }
+
+            match self.ffmpeg.read_exact(buf) {
+                Ok(()) => {
+                    self.received_audio = true;
+                    break;
+                }
+                // A pass that produced nothing would just loop forever, say with `start` past
+                // the end of the file.
+                Err(AudioCaptureError::EndOfStream) if self.options.looping && self.received_audio => {
+                    self.restart()?;
+                }
+                Err(AudioCaptureError::DeviceOpen { stderr, .. }) => {
+                    return Err(AudioCaptureError::MediaOpen {
+                        path: self.path.display().to_string(),
+                        stderr,
+                    });
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        if let Some(pacer) = &mut self.pacer {
+            pacer.wait(buf.len() / self.config.bytes_per_frame());
+        }
+
+        Ok(())
+    }
+
+    fn stop_handle(&self) -> StopHandle {
+        let stopped = Arc::clone(&self.stopped);
+        let current_stop = Arc::clone(&self.current_stop);
+        StopHandle::new(move || {
+            stopped.store(true, Ordering::SeqCst);
+            lock(&current_stop).stop();
+        })
+    }
+
+    fn is_realtime(&self) -> bool {
+        self.pacer.is_some()
+    }
+}
+
+fn spawn(program: &OsString, path: &Path, config: CaptureConfig, options: MediaOptions) -> Result<FfmpegSource, AudioCaptureError> {
+    FfmpegSource::spawn(program, args(path, config, options), &path.display().to_string(), config)
+}
+
+fn args(path: &Path, config: CaptureConfig, options: MediaOptions) -> Vec<String> {
+    let mut args = Vec::new();
+    if !options.start.is_zero() {
+        // Before -i, so ffmpeg seeks in the input instead of decoding everything up to there.
+        args.extend(["-ss".to_string(), format!("{:.3}", options.start.as_secs_f64())]);
+    }
+    args.extend([
+        "-i".to_string(), path.to_string_lossy().into_owned(),
+        "-vn".to_string(), // Ignore video
+        "-ar".to_string(), config.sample_rate.to_string(),
+        "-ac".to_string(), config.channels.to_string(),
+        "-f".to_string(), config.format.ffmpeg_name().to_string(),
+        "-v".to_string(), "error".to_string(),
+        "-nostdin".to_string(),
+        "pipe:1".to_string(),
+    ]);
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::SampleFormat;
+    use crate::reader::FrameReader;
+    use crate::test_util::temp_path;
+    use std::os::unix::fs::PermissionsExt;
+
+    const CONFIG: CaptureConfig = CaptureConfig { sample_rate: 8000, channels: 1, format: SampleFormat::S16 };
+
+    /// An executable standing in for ffmpeg.
+    fn stub(name: &str, script: &str) -> PathBuf {
+        let path = temp_path(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn seeks_and_converts() {
+        let options = MediaOptions { start: Duration::from_millis(1500), ..MediaOptions::default() };
+        let seeking = args(Path::new("track.flac"), CONFIG, options);
+        assert_eq!(seeking[..4], ["-ss", "1.500", "-i", "track.flac"]);
+        assert!(seeking.windows(2).any(|pair| pair == ["-f", "s16le"]));
+
+        let from_start = args(Path::new("track.flac"), CONFIG, MediaOptions::default());
+        assert_eq!(from_start[0], "-i");
+    }
+
+    #[test]
+    fn loops_until_stopped() {
+        let pcm = temp_path("loop.pcm");
+        std::fs::write(&pcm, [1i16, 2, 3, 4, 5].iter().flat_map(|s| s.to_le_bytes()).collect::<Vec<_>>()).unwrap();
+        let program = stub("loop.sh", &format!("cat '{}'", pcm.display()));
+
+        let options = MediaOptions { looping: true, pacing: Pacing::Unthrottled, ..MediaOptions::default() };
+        let mut source = MediaSource::with_program(&program, &pcm, CONFIG, options).unwrap();
+        let mut reader = FrameReader::<i16>::new(CONFIG, 2);
+
+        // The odd sample out at the end of every pass is dropped.
+        let samples: Vec<i16> = (0..4).flat_map(|_| reader.read(&mut source).unwrap().interleaved().collect::<Vec<_>>()).collect();
+        assert_eq!(samples, [1, 2, 3, 4, 1, 2, 3, 4]);
+
+        source.stop_handle().stop();
+        assert!(matches!(reader.read(&mut source), Err(AudioCaptureError::Stopped)));
+
+        // Without looping, the file just ends.
+        let options = MediaOptions { pacing: Pacing::Unthrottled, ..MediaOptions::default() };
+        let mut source = MediaSource::with_program(&program, &pcm, CONFIG, options).unwrap();
+        reader.read(&mut source).unwrap();
+        reader.read(&mut source).unwrap();
+        assert!(matches!(reader.read(&mut source), Err(AudioCaptureError::EndOfStream)));
+
+        let _ = std::fs::remove_file(pcm);
+        let _ = std::fs::remove_file(program);
+    }
+
+    #[test]
+    fn undecodable_file() {
+        let program = stub("undecodable.sh", "echo \"$2: Invalid data found when processing input\" >&2; exit 1");
+        let path = temp_path("undecodable.mp3");
+        std::fs::write(&path, b"not an mp3").unwrap();
+
+        let mut source = MediaSource::with_program(&program, &path, CONFIG, MediaOptions::default()).unwrap();
+        match source.read_exact(&mut [0; 64]) {
+            Err(AudioCaptureError::MediaOpen { path: failed, stderr }) => {
+                assert_eq!(failed, path.display().to_string());
+                assert_eq!(stderr, format!("{}: Invalid data found when processing input", path.display()));
+            }
+            other => panic!("expected MediaOpen, got {:?}", other.err()),
+        }
+
+        assert!(matches!(
+            MediaSource::open(temp_path("missing.mp3"), CONFIG, MediaOptions::default()),
+            Err(AudioCaptureError::ReadError(..))
+        ));
+
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(program);
+    }
+}
@@ -0,0 +1,205 @@
+use std::time::Duration;
+use crate::format::Sample;
+use crate::frame::Frame;
+
+/// How a [`Meter`] holds peaks and detects clipping.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeterConfig {
+    /// How long the held peak stays put before it starts to fall.
+    pub hold: Duration,
+    /// How fast the held peak falls after that, in dB per second.
+    pub decay: f32,
+    /// Samples at or above this level (relative to full scale) count as clipped.
+    pub clip_level: f32,
+}
+
+impl Default for MeterConfig {
+    fn default() -> Self {
+        MeterConfig {
+            hold: Duration::from_secs(1),
+            decay: 20.0,
+            // Just below full scale, since the largest positive 16-bit sample is 32767/32768.
+            clip_level: 0.999,
+        }
+    }
+}
+
+/// The levels of one channel, relative to full scale (1.0).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChannelLevels {
+    /// RMS of the last frame.
+    pub rms: f32,
+    /// Largest absolute sample in the last frame.
+    pub peak: f32,
+    /// The largest recent peak, held and then decaying as set up in [`MeterConfig`].
+    pub peak_hold: f32,
+    /// How many samples of the last frame clipped.
+    pub clipped: usize,
+    /// How many samples clipped since metering started.
+    pub total_clipped: u64,
+}
+
+impl ChannelLevels {
+    pub fn rms_db(&self) -> f32 {
+        to_dbfs(self.rms)
+    }
+
+    pub fn peak_db(&self) -> f32 {
+        to_dbfs(self.peak)
+    }
+
+    pub fn peak_hold_db(&self) -> f32 {
+        to_dbfs(self.peak_hold)
+    }
+
+    /// Peak over RMS. About 1.41 for a sine, much higher for percussive material. `None` for
+    /// silence.
+    pub fn crest_factor(&self) -> Option<f32> {
+        (self.rms > 0.0).then(|| self.peak / self.rms)
+    }
+}
+
+/// Per-channel levels of the last metered frame.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Levels {
+    pub channels: Vec<ChannelLevels>,
+    /// Position of the last metered frame in the stream, see [`Frame::sample_index`].
+    pub sample_index: u64,
+}
+
+impl Levels {
+    /// Whether any channel clipped in the last frame.
+    pub fn clipped(&self) -> bool {
+        self.channels.iter().any(|channel| channel.clipped > 0)
+    }
+}
+
+/// Level in dBFS. Silence is negative infinity.
+pub fn to_dbfs(level: f32) -> f32 {
+    20.0 * level.log10()
+}
+
+/// Measures levels frame by frame, without an FFT. Every
+/// [`AudioCapturer`](crate::AudioCapturer) runs one, see
+/// [`AudioCapturer::levels`](crate::AudioCapturer::levels).
+#[derive(Debug, Clone)]
+pub struct Meter {
+    config: MeterConfig,
+    levels: Levels,
+    /// Time since each channel's held peak was set.
+    held_for: Vec<Duration>,
+}
+
+impl Meter {
+    pub fn new(config: MeterConfig) -> Self {
+        Meter {
+            config,
+            levels: Levels::default(),
+            held_for: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> MeterConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: MeterConfig) {
+        self.config = config;
+    }
+
+    pub fn levels(&self) -> &Levels {
+        &self.levels
+    }
+
+    /// Meters `frame`. Peaks are held and decay in stream time, so this works the same whether
+    /// audio arrives in real time or not.
+    pub fn update<T: Sample>(&mut self, frame: &Frame<T>) {
+        let channels = frame.channels() as usize;
+        if self.levels.channels.len() != channels {
+            self.levels.channels = vec![ChannelLevels::default(); channels];
+            self.held_for = vec![Duration::ZERO; channels];
+        }
+
+        let elapsed = frame.duration();
+        for (c, (levels, held_for)) in self.levels.channels.iter_mut().zip(&mut self.held_for).enumerate() {
+            let mut sum_of_squares = 0.0;
+            let mut peak = 0.0f32;
+            let mut clipped = 0;
+            for sample in frame.channel(c) {
+                let value = sample.to_f32().abs();
+                sum_of_squares += value * value;
+                peak = peak.max(value);
+                if value >= self.config.clip_level {
+                    clipped += 1;
+                }
+            }
+
+            levels.rms = if frame.is_empty() { 0.0 } else { (sum_of_squares / frame.len() as f32).sqrt() };
+            levels.peak = peak;
+            levels.clipped = clipped;
+            levels.total_clipped += clipped as u64;
+
+            *held_for += elapsed;
+            if peak >= levels.peak_hold {
+                levels.peak_hold = peak;
+                *held_for = Duration::ZERO;
+            } else if *held_for > self.config.hold {
+                // Only decay for the part of this frame that is past the hold time.
+                let decaying = (*held_for - self.config.hold).min(elapsed);
+                let decayed = levels.peak_hold * 10f32.powf(-self.config.decay * decaying.as_secs_f32() / 20.0);
+                levels.peak_hold = decayed.max(peak);
+            }
+        }
+        self.levels.sample_index = frame.sample_index();
+    }
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Meter::new(MeterConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_and_peak_hold() {
+        let rate = 1000;
+        // A 10 ms frame of a half-scale sine on the left, silence on the right.
+        let sine: Vec<f32> = (0..10).map(|i| 0.5 * (2.0 * std::f32::consts::PI * i as f32 / 10.0).sin()).collect();
+        let loud = Frame::from_planar([sine, vec![0.0; 10]].concat(), 2, rate, 10);
+        let quiet = Frame::from_planar(vec![0.0f32; 20], 2, rate, 10);
+
+        let mut meter = Meter::new(MeterConfig { hold: Duration::from_millis(50), decay: 100.0, clip_level: 0.999 });
+        meter.update(&loud);
+        let left = meter.levels().channels[0];
+        assert!((left.rms - 0.5 / 2f32.sqrt()).abs() < 1e-4);
+        assert!((left.peak - 0.5).abs() < 0.03);
+        assert!((left.crest_factor().unwrap() - 2f32.sqrt()).abs() < 0.1);
+        assert_eq!(meter.levels().channels[1].crest_factor(), None);
+        assert_eq!(meter.levels().channels[1].rms_db(), f32::NEG_INFINITY);
+
+        // Held for 50 ms...
+        for _ in 0..5 {
+            meter.update(&quiet);
+        }
+        assert_eq!(meter.levels().channels[0].peak_hold, left.peak);
+        assert_eq!(meter.levels().channels[0].peak, 0.0);
+
+        // ...then falling at 100 dB/s, 1 dB per frame.
+        meter.update(&quiet);
+        meter.update(&quiet);
+        let fallen = left.peak_hold_db() - meter.levels().channels[0].peak_hold_db();
+        assert!((fallen - 2.0).abs() < 1e-3, "fell {} dB", fallen);
+        assert!(!meter.levels().clipped());
+
+        let clipping = Frame::new(&[i16::MAX, i16::MIN, 0, i16::MIN], 2, rate);
+        meter.update(&clipping);
+        assert_eq!(meter.levels().channels[0].clipped, 1);
+        assert_eq!(meter.levels().channels[1].clipped, 2);
+        assert!(meter.levels().clipped());
+        assert_eq!(meter.levels().channels[0].peak_hold, meter.levels().channels[0].peak);
+    }
+}
@@ -0,0 +1,197 @@
+use std::io::{ErrorKind, Read};
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use crate::format::CaptureConfig;
+use crate::source::{AudioSource, Pacer, StopHandle};
+use crate::wav::Pacing;
+use crate::AudioCaptureError;
+
+/// Raw interleaved PCM in a declared format from anything readable: stdin, a named pipe, a TCP
+/// socket, a file. Handy for piping in audio from tools like `parec` or `sox`:
+///
+/// ```text
+/// parec --format=s16le --rate=44100 --channels=2 | my-visualizer
+/// ```
+///
+/// The stream ends with [`AudioCaptureError::EndOfStream`] once the reader runs dry, dropping a
+/// final partial buffer. Stopping takes effect before the next read, so a read that is blocked
+/// waiting for data keeps blocking until data comes in or the other end closes, unless the source
+/// was made [`interruptible`](Self::interruptible).
+pub struct PcmSource<R> {
+    reader: R,
+    config: CaptureConfig,
+    pacer: Option<Pacer>,
+    realtime: bool,
+    stopped: Arc<AtomicBool>,
+    interrupt: Option<StopHandle>,
+}
+
+impl<R: Read + Send> PcmSource<R> {
+    /// A live stream, paced by whoever writes to it.
+    ///
+    /// Unless it is made [`interruptible`](Self::interruptible), an
+    /// [`AudioCapturer`](crate::AudioCapturer) that is dropped during a read leaves the read,
+    /// its thread and the reader behind until data comes in or the other end closes.
+    pub fn new(reader: R, config: CaptureConfig) -> Self {
+        PcmSource {
+            reader,
+            config,
+            pacer: None,
+            realtime: true,
+            stopped: Arc::new(AtomicBool::new(false)),
+            interrupt: None,
+        }
+    }
+
+    /// Lets stopping cut short a read that is waiting for data, by also calling `interrupt`.
+    /// It has to make the reader return, say by shutting down a socket:
+    ///
+    /// ```no_run
+    /// # use audio_experiments::{CaptureConfig, PcmSource};
+    /// # let stream = std::net::TcpStream::connect("127.0.0.1:4000").unwrap();
+    /// let handle = stream.try_clone().unwrap();
+    /// let source = PcmSource::new(stream, CaptureConfig::default())
+    ///     .interruptible(move || { let _ = handle.shutdown(std::net::Shutdown::Both); });
+    /// ```
+    pub fn interruptible(mut self, interrupt: impl Fn() + Send + Sync + 'static) -> Self {
+        self.interrupt = Some(StopHandle::new(interrupt));
+        self
+    }
+
+    /// For readers that have all their audio ready (like files): hand it out in real time, or
+    /// as fast as it is consumed.
+    pub fn paced(mut self, pacing: Pacing) -> Self {
+        self.pacer = match pacing {
+            Pacing::RealTime => Some(Pacer::new(self.config.sample_rate)),
+            Pacing::Unthrottled => None,
+        };
+        self.realtime = pacing == Pacing::RealTime;
+        self
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read + Send> AudioSource for PcmSource<R> {
+    fn config(&self) -> CaptureConfig {
+        self.config
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), AudioCaptureError> {
+        if self.stopped.load(Ordering::SeqCst) {
+            return Err(AudioCaptureError::Stopped);
+        }
+
+        match self.reader.read_exact(buf) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Err(AudioCaptureError::EndOfStream),
+            Err(e) => return Err(AudioCaptureError::ReadError(e)),
+        }
+
+        if let Some(pacer) = &mut self.pacer {
+            pacer.wait(buf.len() / self.config.bytes_per_frame());
+        }
+
+        Ok(())
+    }
+
+    fn stop_handle(&self) -> StopHandle {
+        let stopped = Arc::clone(&self.stopped);
+        let interrupt = self.interrupt.clone();
+        StopHandle::new(move || {
+            stopped.store(true, Ordering::SeqCst);
+            if let Some(interrupt) = &interrupt {
+                interrupt.stop();
+            }
+        })
+    }
+
+    fn is_realtime(&self) -> bool {
+        self.realtime
+    }
+
+    fn can_interrupt(&self) -> bool {
+        self.interrupt.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::SampleFormat;
+    use crate::AudioCapturer;
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn capture_from_a_socket() {
+        let config = CaptureConfig { sample_rate: 8000, channels: 2, format: SampleFormat::F32 };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let sender = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            // Two frames of two stereo samples, and half a sample that gets dropped.
+            for sample in [0.5f32, -0.5, 0.25, -0.25, 1.0, -1.0, 0.0, 0.0] {
+                stream.write_all(&sample.to_le_bytes()).unwrap();
+            }
+            stream.write_all(&[0, 0]).unwrap();
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let capturer = AudioCapturer::<f32>::with_source(PcmSource::new(stream, config).paced(Pacing::Unthrottled), 2).unwrap();
+        sender.join().unwrap();
+
+        let first = capturer.read_frame().unwrap();
+        assert_eq!(first.left(), &[0.5, 0.25]);
+        assert_eq!(first.right(), &[-0.5, -0.25]);
+        let second = capturer.read_frame().unwrap();
+        assert_eq!(second.left(), &[1.0, 0.0]);
+        assert!(matches!(capturer.read_frame(), Err(AudioCaptureError::EndOfStream)));
+    }
+
+    #[test]
+    fn stop_a_quiet_socket() {
+        let config = CaptureConfig { sample_rate: 8000, channels: 1, format: SampleFormat::S16 };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let quiet = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let also_quiet = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let closing = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+
+        // Stopping interrupts the read that is waiting for data.
+        let (stream, _) = listener.accept().unwrap();
+        let handle = stream.try_clone().unwrap();
+        let source = PcmSource::new(stream, config).interruptible(move || {
+            let _ = handle.shutdown(std::net::Shutdown::Both);
+        });
+        let mut capturer = AudioCapturer::<i16>::with_source(source, 80).unwrap();
+        let _ = capturer.try_read_frame().unwrap();
+        let start = std::time::Instant::now();
+        capturer.stop().unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+        // Without a way to interrupt it, dropping the capturer leaves the read behind instead of
+        // waiting for it.
+        let (stream, _) = listener.accept().unwrap();
+        let capturer = AudioCapturer::<i16>::with_source(PcmSource::new(stream, config), 80).unwrap();
+        let _ = capturer.try_read_frame().unwrap();
+        let start = std::time::Instant::now();
+        drop(capturer);
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+        // ...but stopping it explicitly still waits for the read to return.
+        let (stream, _) = listener.accept().unwrap();
+        let mut capturer = AudioCapturer::<i16>::with_source(PcmSource::new(stream, config), 80).unwrap();
+        let stopper = std::thread::spawn(move || capturer.stop());
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!stopper.is_finished());
+        drop(closing);
+        assert!(stopper.join().unwrap().is_ok());
+        drop((quiet, also_quiet));
+    }
+}
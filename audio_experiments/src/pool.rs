@@ -0,0 +1,99 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use crate::sync::lock;
+
+/// How many spare buffers a pool holds on to. Anything returned beyond this is freed.
+const MAX_SPARE_BUFFERS: usize = 256;
+
+/// Recycles sample buffers, so the reader thread doesn't have to allocate for every frame.
+pub(crate) struct BufferPool<T> {
+    spare: Mutex<Vec<Vec<T>>>,
+}
+
+impl<T: Copy + Default> BufferPool<T> {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(BufferPool {
+            spare: Mutex::new(Vec::with_capacity(MAX_SPARE_BUFFERS)),
+        })
+    }
+
+    /// Hands out a buffer of `len` samples. Its contents are whatever the last user left behind.
+    pub(crate) fn get(self: &Arc<Self>, len: usize) -> Buffer<T> {
+        let spare = lock(&self.spare).pop();
+        let mut data = spare.unwrap_or_default();
+        // Buffers from one pool all have the same length, so this only allocates for new ones.
+        data.resize(len, T::default());
+
+        Buffer {
+            data,
+            pool: Some(Arc::clone(self)),
+        }
+    }
+
+    fn put(&self, data: Vec<T>) {
+        let mut spare = lock(&self.spare);
+        if spare.len() < MAX_SPARE_BUFFERS {
+            spare.push(data);
+        }
+    }
+}
+
+/// A sample buffer that goes back to its [`BufferPool`] when dropped.
+pub(crate) struct Buffer<T: Copy + Default> {
+    data: Vec<T>,
+    pool: Option<Arc<BufferPool<T>>>,
+}
+
+impl<T: Copy + Default> Buffer<T> {
+    /// A buffer that doesn't belong to any pool.
+    pub(crate) fn unpooled(data: Vec<T>) -> Self {
+        Buffer { data, pool: None }
+    }
+}
+
+impl<T: Copy + Default> Deref for Buffer<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.data
+    }
+}
+
+impl<T: Copy + Default> DerefMut for Buffer<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.data
+    }
+}
+
+impl<T: Copy + Default> Clone for Buffer<T> {
+    fn clone(&self) -> Self {
+        match &self.pool {
+            Some(pool) => {
+                let mut buffer = pool.get(self.data.len());
+                buffer.copy_from_slice(&self.data);
+                buffer
+            }
+            None => Buffer::unpooled(self.data.clone()),
+        }
+    }
+}
+
+impl<T: Copy + Default + PartialEq> PartialEq for Buffer<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data
+    }
+}
+
+impl<T: Copy + Default + std::fmt::Debug> std::fmt::Debug for Buffer<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.data.fmt(f)
+    }
+}
+
+impl<T: Copy + Default> Drop for Buffer<T> {
+    fn drop(&mut self) {
+        if let Some(pool) = &self.pool {
+            pool.put(std::mem::take(&mut self.data));
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use std::sync::Arc;
+use std::time::Instant;
+use crate::format::{CaptureConfig, Sample};
+use crate::frame::Frame;
+use crate::pool::BufferPool;
+use crate::source::AudioSource;
+use crate::AudioCaptureError;
+
+/// Turns raw PCM from a source into frames. Once warmed up it doesn't allocate: the read buffer
+/// is reused and frames are built from pooled buffers that return when the frame is dropped.
+pub(crate) struct FrameReader<T: Sample> {
+    config: CaptureConfig,
+    buffer_size: usize,
+    raw_buf: Vec<u8>,
+    pool: Arc<BufferPool<T>>,
+    sample_index: u64,
+}
+
+impl<T: Sample> FrameReader<T> {
+    pub(crate) fn new(config: CaptureConfig, buffer_size: usize) -> Self {
+        FrameReader {
+            config,
+            buffer_size,
+            raw_buf: vec![0; buffer_size * config.bytes_per_frame()],
+            pool: BufferPool::new(),
+            sample_index: 0,
+        }
+    }
+
+    /// Reads the next `buffer_size` samples per channel from `source`.
+    pub(crate) fn read(&mut self, source: &mut impl AudioSource) -> Result<Frame<T>, AudioCaptureError> {
+        source.read_exact(&mut self.raw_buf)?;
+        let captured_at = Instant::now();
+
+        let format = self.config.format;
+        let channels = self.config.channels as usize;
+        let buffer_size = self.buffer_size;
+
+        // read_exact guarantees that raw_buf is completely filled, so it holds exactly
+        // buffer_size samples for every channel. The samples are interleaved, so de-interleave
+        // them while decoding.
+        let mut planar = self.pool.get(buffer_size * channels);
+        for (i, bytes) in self.raw_buf.chunks_exact(format.bytes_per_sample()).enumerate() {
+            planar[(i % channels) * buffer_size + i / channels] = T::decode(format, bytes);
+        }
+
+        let frame = Frame::from_buffer(planar, self.config.channels, self.config.sample_rate)
+            .with_timing(self.sample_index, captured_at);
+        self.sample_index += buffer_size as u64;
+
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broadcast::{Backpressure, Broadcaster};
+    use crate::format::SampleFormat;
+    use crate::signal::{SignalSource, Waveform};
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    /// Counts allocations made by the current thread.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations() -> u64 {
+        ALLOCATIONS.with(Cell::get)
+    }
+
+    #[test]
+    fn steady_state_does_not_allocate() {
+        let config = CaptureConfig { sample_rate: 48000, channels: 2, format: SampleFormat::S16 };
+        let mut source = SignalSource::new(Waveform::WhiteNoise, 1.0, config);
+        let mut reader = FrameReader::<f32>::new(config, 64);
+
+        // Everything the reader thread does for a frame: read, decode, hand the frame to a
+        // couple of subscribers, who then drop it.
+        let broadcaster = Broadcaster::new();
+        let latest = broadcaster.subscribe(1, Backpressure::DropOldest);
+        let queued = broadcaster.subscribe(4, Backpressure::DropNewest);
+        let mut step = || {
+            broadcaster.send(reader.read(&mut source).unwrap());
+            latest.try_recv().unwrap();
+            let _ = queued.try_recv();
+        };
+
+        // Warm up the pool.
+        for _ in 0..16 {
+            step();
+        }
+
+        let before = allocations();
+        for _ in 0..200 {
+            step();
+        }
+        assert_eq!(allocations() - before, 0);
+    }
+}
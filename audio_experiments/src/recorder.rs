@@ -0,0 +1,104 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use crate::broadcast::Subscription;
+use crate::format::{CaptureConfig, Sample};
+use crate::frame::Frame;
+use crate::wav::WavWriter;
+use crate::AudioCaptureError;
+
+/// Writes everything an [`AudioCapturer`](crate::AudioCapturer) captures to WAV files, see
+/// [`AudioCapturer::record`](crate::AudioCapturer::record).
+pub struct Recorder {
+    do_record: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<Vec<PathBuf>, AudioCaptureError>>>,
+}
+
+impl Recorder {
+    pub(crate) fn start<T: Sample>(
+        frames: Subscription<Frame<T>>,
+        path: &Path,
+        config: CaptureConfig,
+        rotate_every: Option<Duration>,
+    ) -> Result<Self, AudioCaptureError> {
+        let do_record = Arc::new(AtomicBool::new(true));
+
+        // Open the first file right away, so a bad path is reported to the caller.
+        let first_path = file_path(path, rotate_every.map(|_| 0));
+        let writer = WavWriter::create(&first_path, config)?;
+
+        let thread = {
+            let do_record = Arc::clone(&do_record);
+            let path = path.to_owned();
+
+            std::thread::spawn(move || {
+                let mut writer = writer;
+                let mut paths = vec![first_path];
+
+                loop {
+                    let frame = match frames.recv_while(&do_record) {
+                        Some(frame) => frame,
+                        // Audio that was captured before we were stopped still belongs in the
+                        // recording.
+                        None => match frames.try_recv() {
+                            Ok(frame) => frame,
+                            Err(..) => break,
+                        },
+                    };
+
+                    if rotate_every.is_some_and(|rotate_every| writer.duration() >= rotate_every) {
+                        let next_path = file_path(&path, Some(paths.len()));
+                        let next = WavWriter::create(&next_path, config)?;
+                        std::mem::replace(&mut writer, next).finish()?;
+                        paths.push(next_path);
+                    }
+
+                    writer.write_frame(&frame)?;
+                }
+
+                writer.finish()?;
+                Ok(paths)
+            })
+        };
+
+        Ok(Recorder {
+            do_record,
+            thread: Some(thread),
+        })
+    }
+
+    /// Stops recording and finalizes the current file. Returns every file written, in order.
+    pub fn stop(mut self) -> Result<Vec<PathBuf>, AudioCaptureError> {
+        self.do_record.store(false, Ordering::SeqCst);
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(..)) => Err(AudioCaptureError::RecordError(std::io::Error::other("recording thread panicked"))),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.do_record.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// `take.wav` stays `take.wav` without rotation, and becomes `take-0000.wav`, `take-0001.wav`,
+/// ... with it.
+fn file_path(path: &Path, index: Option<usize>) -> PathBuf {
+    let Some(index) = index else {
+        return path.to_owned();
+    };
+
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{:04}.{}", stem, index, extension.to_string_lossy()),
+        None => format!("{}-{:04}", stem, index),
+    };
+    path.with_file_name(name)
+}
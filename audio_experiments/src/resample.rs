@@ -0,0 +1,272 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+use crate::broadcast::Subscription;
+use crate::format::Sample;
+use crate::frame::Frame;
+use crate::pool::BufferPool;
+use crate::AudioCaptureError;
+
+/// Zero crossings of the sinc kernel on each side of the output sample.
+const SINC_ZERO_CROSSINGS: usize = 16;
+
+/// How a [`Resampler`] interpolates between input samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResampleQuality {
+    /// Straight lines between neighbouring samples. Cheap, but dulls the highs and lets
+    /// everything above the new Nyquist frequency alias when downsampling.
+    Linear,
+    /// A Blackman-windowed sinc, low-passed at the lower of both Nyquist frequencies.
+    WindowedSinc,
+}
+
+/// Converts a stream of frames to `target_rate`, whatever rate they come in at. Feed it frames
+/// in order; it keeps enough of the stream around to interpolate across frame boundaries.
+pub struct Resampler<T: Sample> {
+    target_rate: u32,
+    quality: ResampleQuality,
+    source_rate: u32,
+    /// Input samples still needed, one Vec per channel, starting with `support` samples of
+    /// context before the next output.
+    history: Vec<Vec<f32>>,
+    /// Position of the next output sample in `history`, in input samples.
+    position: f64,
+    /// Input samples per output sample.
+    step: f64,
+    /// Low-pass cutoff relative to the input Nyquist frequency.
+    cutoff: f64,
+    /// How many input samples on each side of the output sample the kernel reaches.
+    support: usize,
+    /// Index of the next input sample we expect.
+    next_input: u64,
+    /// Index of the next output sample.
+    next_output: u64,
+    pool: Arc<BufferPool<T>>,
+}
+
+impl<T: Sample> Resampler<T> {
+    pub fn new(target_rate: u32, quality: ResampleQuality) -> Result<Self, AudioCaptureError> {
+        if target_rate == 0 {
+            return Err(AudioCaptureError::InvalidConfig("resampling to 0 Hz".into()));
+        }
+
+        Ok(Resampler {
+            target_rate,
+            quality,
+            source_rate: 0,
+            history: Vec::new(),
+            position: 0.0,
+            step: 1.0,
+            cutoff: 1.0,
+            support: 1,
+            next_input: 0,
+            next_output: 0,
+            pool: BufferPool::new(),
+        })
+    }
+
+    pub fn target_rate(&self) -> u32 {
+        self.target_rate
+    }
+
+    /// Starts over for a stream at `frame`'s rate and position.
+    fn reset(&mut self, frame: &Frame<T>) {
+        self.source_rate = frame.sample_rate();
+        self.step = self.source_rate as f64 / self.target_rate as f64;
+        // When downsampling, everything above the new Nyquist frequency has to go.
+        self.cutoff = (1.0 / self.step).min(1.0);
+        self.support = match self.quality {
+            ResampleQuality::Linear => 1,
+            ResampleQuality::WindowedSinc => (SINC_ZERO_CROSSINGS as f64 / self.cutoff).ceil() as usize,
+        };
+
+        // Silence before the start of the stream, so the first outputs have context too.
+        self.history = vec![vec![0.0; self.support]; frame.channels() as usize];
+        self.position = self.support as f64;
+        self.next_input = frame.sample_index();
+        self.next_output = (frame.sample_index() as f64 / self.step).ceil() as u64;
+        // The first output lines up with its input sample.
+        self.position += self.next_output as f64 * self.step - frame.sample_index() as f64;
+    }
+
+    /// Resamples `frame`. Returns the output it completes, if any; sinc interpolation needs to
+    /// see a few samples past each output, so the output lags the input slightly. Frames
+    /// already at the target rate are passed through.
+    pub fn process(&mut self, frame: &Frame<T>) -> Option<Frame<T>> {
+        if frame.sample_rate() == self.target_rate {
+            return Some(frame.clone());
+        }
+
+        if frame.sample_rate() != self.source_rate
+            || frame.channels() as usize != self.history.len()
+            || frame.sample_index() != self.next_input
+        {
+            self.reset(frame);
+        }
+        self.next_input = frame.sample_index() + frame.len() as u64;
+
+        for (c, channel) in self.history.iter_mut().enumerate() {
+            channel.extend(frame.channel(c).iter().map(|sample| sample.to_f32()));
+        }
+
+        // An output needs `support` input samples after it.
+        let available = self.history[0].len();
+        let outputs = if self.position + (self.support as f64) < available as f64 {
+            ((available - self.support) as f64 - self.position) / self.step
+        } else {
+            0.0
+        }
+        .ceil() as usize;
+        if outputs == 0 {
+            return None;
+        }
+
+        let channels = self.history.len();
+        let mut planar = self.pool.get(outputs * channels);
+        for (c, channel) in self.history.iter().enumerate() {
+            for i in 0..outputs {
+                let position = self.position + i as f64 * self.step;
+                planar[c * outputs + i] = T::from_f32(self.interpolate(channel, position));
+            }
+        }
+        let output = Frame::from_buffer(planar, channels as u16, self.target_rate)
+            .with_timing(self.next_output, frame.captured_at());
+        self.next_output += outputs as u64;
+
+        // Forget what no future output reaches.
+        self.position += outputs as f64 * self.step;
+        let consumed = (self.position.floor() as usize).saturating_sub(self.support);
+        for channel in &mut self.history {
+            channel.drain(..consumed);
+        }
+        self.position -= consumed as f64;
+
+        Some(output)
+    }
+
+    fn interpolate(&self, samples: &[f32], position: f64) -> f32 {
+        let index = position.floor() as usize;
+        let fraction = position - index as f64;
+
+        match self.quality {
+            ResampleQuality::Linear => {
+                let next = samples.get(index + 1).copied().unwrap_or(samples[index]);
+                samples[index] + (next - samples[index]) * fraction as f32
+            }
+            ResampleQuality::WindowedSinc => {
+                let support = self.support as f64;
+                let first = index + 1 - self.support;
+                let mut sum = 0.0;
+                for (i, &sample) in samples[first..=index + self.support].iter().enumerate() {
+                    let t = position - (first + i) as f64;
+                    sum += sample as f64 * self.cutoff * sinc(self.cutoff * t) * blackman(t / support);
+                }
+                sum as f32
+            }
+        }
+    }
+}
+
+/// Everything an [`AudioCapturer`](crate::AudioCapturer) captures, at another sample rate, see
+/// [`AudioCapturer::resampled`](crate::AudioCapturer::resampled).
+pub struct Resampled<T: Sample> {
+    frames: Subscription<Frame<T>>,
+    resampler: Resampler<T>,
+}
+
+impl<T: Sample> Resampled<T> {
+    pub(crate) fn new(frames: Subscription<Frame<T>>, resampler: Resampler<T>) -> Self {
+        Resampled { frames, resampler }
+    }
+
+    /// Blocks until the next resampled frame is ready. `None` once capturing has ended.
+    pub fn next_frame(&mut self) -> Option<Frame<T>> {
+        loop {
+            let frame = self.frames.recv().ok()?;
+            if let Some(resampled) = self.resampler.process(&frame) {
+                return Some(resampled);
+            }
+        }
+    }
+
+    /// Like [`next_frame`](Self::next_frame), but returns `None` right away if no frame is
+    /// ready.
+    pub fn try_next_frame(&mut self) -> Option<Frame<T>> {
+        loop {
+            let frame = self.frames.try_recv().ok()?;
+            if let Some(resampled) = self.resampler.process(&frame) {
+                return Some(resampled);
+            }
+        }
+    }
+
+    /// How many frames were lost because resampled frames weren't taken fast enough.
+    pub fn dropped(&self) -> u64 {
+        self.frames.dropped()
+    }
+}
+
+impl<T: Sample> Iterator for Resampled<T> {
+    type Item = Frame<T>;
+
+    fn next(&mut self) -> Option<Frame<T>> {
+        self.next_frame()
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Blackman window over `-1..=1`.
+fn blackman(x: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        return 0.0;
+    }
+    let phase = PI * (x + 1.0);
+    0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a sine through a resampler in frames of 441 samples.
+    fn resample_sine(frequency: f64, from: u32, to: u32, quality: ResampleQuality) -> Vec<f32> {
+        let mut resampler = Resampler::<f32>::new(to, quality).unwrap();
+        let mut output = Vec::new();
+        for start in (0..from as u64 / 2).step_by(441) {
+            let samples: Vec<f32> = (start..start + 441)
+                .map(|i| (2.0 * PI * frequency * i as f64 / from as f64).sin() as f32 * 0.5)
+                .collect();
+            let frame = Frame::new(&samples, 1, from).with_timing(start, std::time::Instant::now());
+            if let Some(resampled) = resampler.process(&frame) {
+                assert_eq!(resampled.sample_index(), output.len() as u64);
+                output.extend_from_slice(resampled.channel(0));
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn resamples_sines() {
+        for (quality, tolerance) in [(ResampleQuality::Linear, 5e-3), (ResampleQuality::WindowedSinc, 1e-3)] {
+            let output = resample_sine(1000.0, 44100, 48000, quality);
+            assert!(output.len() > 23000);
+            // Away from the start, which is faded in from silence.
+            for (i, &sample) in output.iter().enumerate().skip(100) {
+                let expected = (2.0 * PI * 1000.0 * i as f64 / 48000.0).sin() as f32 * 0.5;
+                assert!((sample - expected).abs() < tolerance, "{:?} off by {} at {}", quality, sample - expected, i);
+            }
+        }
+
+        // 6 kHz can't be represented at 8 kHz, so it has to be filtered out instead of aliasing
+        // down to 2 kHz.
+        let aliased = resample_sine(6000.0, 48000, 8000, ResampleQuality::WindowedSinc);
+        let peak = aliased.iter().skip(100).fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!(peak < 0.01, "peak {}", peak);
+    }
+}
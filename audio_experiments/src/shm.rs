@@ -0,0 +1,457 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::sync::{Arc, atomic::{fence, AtomicBool, AtomicU32, AtomicU64, Ordering}};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use crate::broadcast::Subscription;
+use crate::format::{CaptureConfig, Sample, SampleFormat};
+use crate::frame::Frame;
+use crate::AudioCaptureError;
+
+/// Marks a shared memory object as one of our rings, and which layout it has.
+const MAGIC: u64 = u64::from_le_bytes(*b"AUDRING1");
+/// Room for the header; the samples start after it.
+const HEADER_SIZE: usize = 64;
+
+/// Laid out at the start of the shared memory.
+#[repr(C)]
+struct Header {
+    /// [`MAGIC`] once the writer has filled in the rest.
+    magic: AtomicU64,
+    sample_rate: u32,
+    channels: u32,
+    /// Sample frames the ring holds.
+    capacity: u64,
+    /// Sample frames the writer has started writing. Readers check this after copying to see
+    /// whether the writer came around and overwrote what they were reading.
+    writing: AtomicU64,
+    /// Sample frames written so far. The newest is at `written - 1`, modulo `capacity`.
+    written: AtomicU64,
+    /// Set once the writer is gone.
+    closed: AtomicU32,
+}
+
+const _: () = assert!(std::mem::size_of::<Header>() <= HEADER_SIZE);
+
+/// A shared memory object mapped into this process.
+struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is only accessed through atomics.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    fn new(file: &File, len: usize, writable: bool) -> io::Result<Self> {
+        let protection = if writable { libc::PROT_READ | libc::PROT_WRITE } else { libc::PROT_READ };
+        // SAFETY: Mapping a file we hold open; the result is checked before use.
+        let ptr = unsafe { libc::mmap(std::ptr::null_mut(), len, protection, libc::MAP_SHARED, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping { ptr, len })
+    }
+
+    fn header(&self) -> &Header {
+        // SAFETY: Every mapping is at least HEADER_SIZE long and page aligned.
+        unsafe { &*(self.ptr as *const Header) }
+    }
+
+    /// The samples, as f32 bits. Stored as atomics, so readers and the writer can touch the
+    /// same sample at the same time without it being undefined behavior.
+    fn samples(&self) -> &[AtomicU32] {
+        let len = (self.len - HEADER_SIZE) / std::mem::size_of::<AtomicU32>();
+        // SAFETY: The rest of the mapping after the header holds exactly `len` samples.
+        unsafe { std::slice::from_raw_parts(self.ptr.cast::<u8>().add(HEADER_SIZE) as *const AtomicU32, len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: Nothing borrowed from the mapping outlives it.
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+/// Turns a ring name like `desktop` into a shared memory object name.
+fn object_name(name: &str) -> Result<CString, AudioCaptureError> {
+    if name.is_empty() || name.contains('/') {
+        return Err(AudioCaptureError::InvalidConfig(format!("invalid ring name {:?}", name)));
+    }
+    CString::new(format!("/audio_experiments.{}", name))
+        .map_err(|_| AudioCaptureError::InvalidConfig(format!("invalid ring name {:?}", name)))
+}
+
+fn shm_open(name: &CString, flags: libc::c_int) -> io::Result<File> {
+    // SAFETY: `name` is a valid C string; the returned fd is checked and then owned by the File.
+    let fd = unsafe { libc::shm_open(name.as_ptr(), flags | libc::O_CLOEXEC, 0o644) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// The producing end of a ring buffer of captured audio in shared memory, which other processes
+/// can attach to by name with [`SharedRingReader`]. There is one writer per ring; it never waits
+/// for readers, readers that fall behind by more than the ring holds lose audio.
+///
+/// Usually fed by [`AudioCapturer::share`](crate::AudioCapturer::share).
+pub struct SharedRingWriter {
+    name: CString,
+    mapping: Mapping,
+    channels: usize,
+    capacity: u64,
+}
+
+impl SharedRingWriter {
+    /// Creates the ring `name`, holding `capacity` sample frames of audio in `config`'s rate and
+    /// channel layout. An existing ring with the same name is replaced; readers attached to it
+    /// keep the old one until they attach again.
+    pub fn create(name: &str, config: CaptureConfig, capacity: usize) -> Result<Self, AudioCaptureError> {
+        if capacity == 0 || config.channels == 0 {
+            return Err(AudioCaptureError::InvalidConfig(format!("ring of {} frames of {:?}", capacity, config)));
+        }
+
+        let object = object_name(name)?;
+        // Left behind by a writer that crashed, or still in use by one we're replacing.
+        // SAFETY: `object` is a valid C string.
+        unsafe {
+            libc::shm_unlink(object.as_ptr());
+        }
+        let file = shm_open(&object, libc::O_RDWR | libc::O_CREAT | libc::O_EXCL).map_err(AudioCaptureError::SharedMemory)?;
+
+        let len = HEADER_SIZE + capacity * config.channels as usize * std::mem::size_of::<AtomicU32>();
+        let mapping = file.set_len(len as u64)
+            .and_then(|()| Mapping::new(&file, len, true))
+            .map_err(|e| {
+                // SAFETY: `object` is a valid C string.
+                unsafe {
+                    libc::shm_unlink(object.as_ptr());
+                }
+                AudioCaptureError::SharedMemory(e)
+            })?;
+
+        // SAFETY: Nobody can see the ring before the magic is set, so the plain fields can still
+        // be written through a raw pointer.
+        unsafe {
+            let header = mapping.ptr as *mut Header;
+            (*header).sample_rate = config.sample_rate;
+            (*header).channels = config.channels as u32;
+            (*header).capacity = capacity as u64;
+        }
+        mapping.header().magic.store(MAGIC, Ordering::Release);
+
+        Ok(SharedRingWriter {
+            name: object,
+            mapping,
+            channels: config.channels as usize,
+            capacity: capacity as u64,
+        })
+    }
+
+    /// Appends `frame`, overwriting the oldest audio once the ring is full. Frames with a
+    /// different channel count than the ring are rejected.
+    pub fn write<T: Sample>(&mut self, frame: &Frame<T>) -> Result<(), AudioCaptureError> {
+        if frame.channels() as usize != self.channels {
+            return Err(AudioCaptureError::InvalidConfig(format!(
+                "{} channel frame for a {} channel ring",
+                frame.channels(),
+                self.channels
+            )));
+        }
+
+        let header = self.mapping.header();
+        let samples = self.mapping.samples();
+        // Only the last `capacity` frames of a huge frame would survive anyway.
+        let skip = (frame.len() as u64).saturating_sub(self.capacity) as usize;
+        // Only this writer stores to `written`.
+        let written = header.written.load(Ordering::Relaxed);
+        let start = written + skip as u64;
+        let end = written + frame.len() as u64;
+
+        // Announce what's about to be overwritten before touching it, see SharedRingReader::copy.
+        header.writing.store(end, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        for (i, index) in (start..end).enumerate() {
+            let slot = (index % self.capacity) as usize * self.channels;
+            for c in 0..self.channels {
+                let value = frame.channel(c)[skip + i].to_f32();
+                samples[slot + c].store(value.to_bits(), Ordering::Relaxed);
+            }
+        }
+
+        header.written.store(end, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl Drop for SharedRingWriter {
+    fn drop(&mut self) {
+        self.mapping.header().closed.store(1, Ordering::Release);
+        // Readers that are attached keep their mapping, new ones can't find the ring anymore.
+        // SAFETY: `name` is a valid C string.
+        unsafe {
+            libc::shm_unlink(self.name.as_ptr());
+        }
+    }
+}
+
+/// Reads audio from a ring that a [`SharedRingWriter`] in some other process (or this one)
+/// publishes. Never blocks the writer: if the reader falls behind by more than the ring holds,
+/// the audio it missed is skipped and counted in [`overruns`](Self::overruns).
+pub struct SharedRingReader {
+    mapping: Mapping,
+    config: CaptureConfig,
+    capacity: u64,
+    /// Index of the next sample frame to read.
+    position: u64,
+    overruns: u64,
+}
+
+impl SharedRingReader {
+    /// Attaches to the ring `name`. Reading starts with audio written from now on.
+    pub fn attach(name: &str) -> Result<Self, AudioCaptureError> {
+        let object = object_name(name)?;
+        let file = shm_open(&object, libc::O_RDONLY).map_err(AudioCaptureError::SharedMemory)?;
+        let len = file.metadata().map_err(AudioCaptureError::SharedMemory)?.len() as usize;
+        let invalid = || AudioCaptureError::SharedMemory(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not an audio ring", name)));
+        if len < HEADER_SIZE {
+            return Err(invalid());
+        }
+
+        let mapping = Mapping::new(&file, len, false).map_err(AudioCaptureError::SharedMemory)?;
+        let header = mapping.header();
+        if header.magic.load(Ordering::Acquire) != MAGIC {
+            return Err(invalid());
+        }
+        let expected_len = HEADER_SIZE as u64 + header.capacity * header.channels as u64 * 4;
+        if header.channels == 0 || header.capacity == 0 || expected_len != len as u64 {
+            return Err(invalid());
+        }
+
+        let config = CaptureConfig {
+            sample_rate: header.sample_rate,
+            channels: header.channels as u16,
+            format: SampleFormat::F32,
+        };
+        let capacity = header.capacity;
+        let position = header.written.load(Ordering::Acquire);
+
+        Ok(SharedRingReader {
+            mapping,
+            config,
+            capacity,
+            position,
+            overruns: 0,
+        })
+    }
+
+    /// The ring's sample rate and channel layout. Samples are stored as f32.
+    pub fn config(&self) -> CaptureConfig {
+        self.config
+    }
+
+    /// How many sample frames were skipped because this reader fell too far behind.
+    pub fn overruns(&self) -> u64 {
+        self.overruns
+    }
+
+    /// Whether the writer is gone. Whatever it wrote can still be read.
+    pub fn is_closed(&self) -> bool {
+        self.mapping.header().closed.load(Ordering::Acquire) != 0
+    }
+
+    /// The next `len` sample frames, or `None` if they haven't all been written yet. Once the
+    /// writer is gone and everything is read, fails with [`AudioCaptureError::EndOfStream`].
+    pub fn read<T: Sample>(&mut self, len: usize) -> Result<Option<Frame<T>>, AudioCaptureError> {
+        if len as u64 > self.capacity {
+            return Err(AudioCaptureError::InvalidConfig(format!("reading {} frames from a ring of {}", len, self.capacity)));
+        }
+
+        loop {
+            // Check before looking at `written`, so audio written right before closing isn't missed.
+            let closed = self.is_closed();
+            let written = self.mapping.header().written.load(Ordering::Acquire);
+            if written - self.position > self.capacity {
+                self.skip_to(written - self.capacity);
+            }
+            if written - self.position < len as u64 {
+                return if closed { Err(AudioCaptureError::EndOfStream) } else { Ok(None) };
+            }
+
+            if let Some(frame) = self.copy(self.position, len) {
+                self.position += len as u64;
+                return Ok(Some(frame));
+            }
+            // The writer overwrote part of it while we were copying, try again further along.
+        }
+    }
+
+    /// Like [`read`](Self::read), but waits up to `timeout` for the audio to come in.
+    pub fn read_timeout<T: Sample>(&mut self, len: usize, timeout: Duration) -> Result<Option<Frame<T>>, AudioCaptureError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(frame) = self.read(len)? {
+                return Ok(Some(frame));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+
+            // Sleep about as long as it takes for the missing audio to be captured.
+            let written = self.mapping.header().written.load(Ordering::Acquire);
+            let missing = (self.position + len as u64).saturating_sub(written);
+            let wait = Duration::from_secs_f64(missing as f64 / self.config.sample_rate as f64)
+                .clamp(Duration::from_millis(1), Duration::from_millis(20));
+            std::thread::sleep(wait.min(deadline - now));
+        }
+    }
+
+    /// The newest `len` sample frames, skipping whatever came before them. `None` if the writer
+    /// hasn't written that much yet.
+    pub fn latest<T: Sample>(&mut self, len: usize) -> Result<Option<Frame<T>>, AudioCaptureError> {
+        let written = self.mapping.header().written.load(Ordering::Acquire);
+        if written >= len as u64 && written - len as u64 > self.position {
+            // Skipping on purpose isn't an overrun.
+            self.position = written - len as u64;
+        }
+        self.read(len)
+    }
+
+    fn skip_to(&mut self, position: u64) {
+        self.overruns += position - self.position;
+        self.position = position;
+    }
+
+    /// Copies `len` sample frames starting at `start`, or returns `None` (after skipping ahead)
+    /// if the writer overwrote any of them meanwhile.
+    fn copy<T: Sample>(&mut self, start: u64, len: usize) -> Option<Frame<T>> {
+        let samples = self.mapping.samples();
+        let channels = self.config.channels as usize;
+
+        let mut planar = vec![T::default(); len * channels];
+        for i in 0..len {
+            let slot = ((start + i as u64) % self.capacity) as usize * channels;
+            for c in 0..channels {
+                planar[c * len + i] = T::from_f32(f32::from_bits(samples[slot + c].load(Ordering::Relaxed)));
+            }
+        }
+
+        // Pairs with the writer's fence: if we read anything it wrote after announcing `writing`,
+        // we're guaranteed to see that announcement here.
+        fence(Ordering::Acquire);
+        let writing = self.mapping.header().writing.load(Ordering::Relaxed);
+        let oldest_intact = writing.saturating_sub(self.capacity);
+        if oldest_intact > start {
+            self.skip_to(oldest_intact);
+            return None;
+        }
+
+        let frame = Frame::from_planar(planar, self.config.channels, self.config.sample_rate, len);
+        Some(frame.with_timing(start, Instant::now()))
+    }
+}
+
+/// Feeds everything an [`AudioCapturer`](crate::AudioCapturer) captures into a
+/// [`SharedRingWriter`], see [`AudioCapturer::share`](crate::AudioCapturer::share).
+pub struct SharedRingPublisher {
+    do_publish: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<(), AudioCaptureError>>>,
+}
+
+impl SharedRingPublisher {
+    pub(crate) fn start<T: Sample>(frames: Subscription<Frame<T>>, mut writer: SharedRingWriter) -> Self {
+        let do_publish = Arc::new(AtomicBool::new(true));
+
+        let thread = {
+            let do_publish = Arc::clone(&do_publish);
+            std::thread::spawn(move || {
+                while let Some(frame) = frames.recv_while(&do_publish) {
+                    writer.write(&frame)?;
+                }
+                // Dropping the writer tells readers the stream has ended.
+                Ok(())
+            })
+        };
+
+        SharedRingPublisher {
+            do_publish,
+            thread: Some(thread),
+        }
+    }
+
+    /// Stops publishing and removes the ring. Attached readers can still read what's in it.
+    pub fn stop(mut self) -> Result<(), AudioCaptureError> {
+        self.do_publish.store(false, Ordering::SeqCst);
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(..)) => Err(AudioCaptureError::SharedMemory(io::Error::other("publishing thread panicked"))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for SharedRingPublisher {
+    fn drop(&mut self) {
+        self.do_publish.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::counting_frame;
+
+    fn ring_name(name: &str) -> String {
+        format!("test_{}_{}", std::process::id(), name)
+    }
+
+    #[test]
+    fn read_across_mappings() {
+        let name = ring_name("read");
+        let config = CaptureConfig { sample_rate: 1000, channels: 2, format: SampleFormat::F32 };
+        let mut writer = SharedRingWriter::create(&name, config, 16).unwrap();
+        let mut reader = SharedRingReader::attach(&name).unwrap();
+        assert_eq!(reader.config(), config);
+
+        assert!(reader.read::<f32>(4).unwrap().is_none());
+        writer.write(&counting_frame::<f32>(0, 6)).unwrap();
+        let frame = reader.read::<f32>(4).unwrap().unwrap();
+        assert_eq!(frame.left(), &[0.0, 1.0, 2.0, 3.0]);
+        assert_eq!(frame.right(), &[0.0, -1.0, -2.0, -3.0]);
+        assert!(reader.read::<f32>(4).unwrap().is_none());
+
+        // Wraps around the end of the ring.
+        writer.write(&counting_frame::<f32>(6, 14)).unwrap();
+        let frame = reader.read::<f32>(8).unwrap().unwrap();
+        assert_eq!(frame.sample_index(), 4);
+        assert_eq!(frame.left(), &[4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0]);
+        assert_eq!(reader.overruns(), 0);
+
+        // Falls behind by more than the ring holds.
+        writer.write(&counting_frame::<f32>(20, 20)).unwrap();
+        let frame = reader.read::<f32>(4).unwrap().unwrap();
+        assert_eq!(frame.left(), &[24.0, 25.0, 26.0, 27.0]);
+        assert_eq!(reader.overruns(), 12);
+
+        let latest = reader.latest::<i16>(2).unwrap().unwrap();
+        assert_eq!(latest.sample_index(), 38);
+        assert_eq!(reader.overruns(), 12);
+
+        drop(writer);
+        assert!(reader.is_closed());
+        assert!(matches!(reader.read::<f32>(1), Err(AudioCaptureError::EndOfStream)));
+        assert!(SharedRingReader::attach(&name).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use std::f32::consts::PI;
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use crate::format::CaptureConfig;
+use crate::source::{AudioSource, Pacer, StopHandle};
+use crate::AudioCaptureError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Waveform {
+    Sine { frequency: f32 },
+    Square { frequency: f32 },
+    Sawtooth { frequency: f32 },
+    WhiteNoise,
+    Silence,
+}
+
+/// Generates a test signal in real time, as if it were coming from a sound card. The same
+/// signal is written to every channel.
+pub struct SignalSource {
+    waveform: Waveform,
+    amplitude: f32,
+    config: CaptureConfig,
+    position: u64,
+    noise_state: u32,
+    pacer: Pacer,
+    stopped: Arc<AtomicBool>,
+}
+
+impl SignalSource {
+    /// `amplitude` is relative to full scale and is clamped to `0.0..=1.0`.
+    pub fn new(waveform: Waveform, amplitude: f32, config: CaptureConfig) -> Self {
+        SignalSource {
+            waveform,
+            amplitude: amplitude.clamp(0.0, 1.0),
+            config,
+            position: 0,
+            noise_state: 0x1234_5678,
+            pacer: Pacer::new(config.sample_rate),
+            stopped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let phase = match self.waveform {
+            Waveform::Sine { frequency }
+            | Waveform::Square { frequency }
+            | Waveform::Sawtooth { frequency } => {
+                // Derived from the sample position rather than accumulated, so it doesn't drift.
+                (self.position as f64 * frequency as f64 / self.config.sample_rate as f64).fract() as f32
+            }
+            Waveform::WhiteNoise | Waveform::Silence => 0.0,
+        };
+        self.position += 1;
+
+        let value = match self.waveform {
+            Waveform::Sine { .. } => (2.0 * PI * phase).sin(),
+            Waveform::Square { .. } => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sawtooth { .. } => 2.0 * phase - 1.0,
+            Waveform::WhiteNoise => {
+                // xorshift32, plenty random for a test signal.
+                self.noise_state ^= self.noise_state << 13;
+                self.noise_state ^= self.noise_state >> 17;
+                self.noise_state ^= self.noise_state << 5;
+                (self.noise_state as f32 / u32::MAX as f32) * 2.0 - 1.0
+            }
+            Waveform::Silence => 0.0,
+        };
+
+        value * self.amplitude
+    }
+}
+
+impl AudioSource for SignalSource {
+    fn config(&self) -> CaptureConfig {
+        self.config
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), AudioCaptureError> {
+        if self.stopped.load(Ordering::SeqCst) {
+            return Err(AudioCaptureError::Stopped);
+        }
+
+        let format = self.config.format;
+        let frame_bytes = self.config.bytes_per_frame();
+        for frame in buf.chunks_exact_mut(frame_bytes) {
+            let sample = self.next_sample();
+            for bytes in frame.chunks_exact_mut(format.bytes_per_sample()) {
+                format.encode(sample, bytes);
+            }
+        }
+
+        self.pacer.wait(buf.len() / frame_bytes);
+        Ok(())
+    }
+
+    fn stop_handle(&self) -> StopHandle {
+        let stopped = Arc::clone(&self.stopped);
+        StopHandle::new(move || stopped.store(true, Ordering::SeqCst))
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::format::CaptureConfig;
+use crate::AudioCaptureError;
+
+/// Something that produces PCM audio for an [`AudioCapturer`](crate::AudioCapturer).
+///
+/// Every source hands out interleaved little-endian samples in the format described by
+/// [`config`](Self::config), so the capturer (and everything downstream of it) doesn't care where
+/// the audio comes from.
+pub trait AudioSource: Send {
+    /// The sample rate, channel count and sample format of the audio this source produces.
+    fn config(&self) -> CaptureConfig;
+
+    /// Fills `buf` completely, blocking until enough audio is available. Returns an error once
+    /// the source has been stopped, has run out of audio or has failed.
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), AudioCaptureError>;
+
+    /// Returns a handle that can stop this source from another thread, even while a read is in
+    /// progress.
+    fn stop_handle(&self) -> StopHandle;
+
+    /// Whether the source produces audio at its own pace, like a sound card. Frames from realtime
+    /// sources are dropped when the consumer falls behind; other sources wait for the consumer.
+    fn is_realtime(&self) -> bool {
+        true
+    }
+
+    /// Whether the [`StopHandle`] can cut short a read that is waiting for audio. A capturer
+    /// that is dropped doesn't wait for sources that can't, and leaves their read to finish in
+    /// the background.
+    fn can_interrupt(&self) -> bool {
+        true
+    }
+}
+
+/// Stops an [`AudioSource`] from outside the thread that is reading from it.
+#[derive(Clone)]
+pub struct StopHandle(Arc<dyn Fn() + Send + Sync>);
+
+impl StopHandle {
+    pub fn new(stop: impl Fn() + Send + Sync + 'static) -> Self {
+        StopHandle(Arc::new(stop))
+    }
+
+    pub fn stop(&self) {
+        (self.0)()
+    }
+}
+
+/// Keeps a source from producing audio faster than it would come out of a sound card.
+pub(crate) struct Pacer {
+    start: Option<Instant>,
+    sample_rate: u32,
+    frames_emitted: u64,
+}
+
+impl Pacer {
+    pub(crate) fn new(sample_rate: u32) -> Self {
+        Pacer {
+            start: None,
+            sample_rate,
+            frames_emitted: 0,
+        }
+    }
+
+    /// Sleeps until `frames` more sample frames are "due" according to the wall clock.
+    pub(crate) fn wait(&mut self, frames: usize) {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        self.frames_emitted += frames as u64;
+
+        let due = start + Duration::from_secs_f64(self.frames_emitted as f64 / self.sample_rate as f64);
+        let now = Instant::now();
+        if due > now {
+            std::thread::sleep(due - now);
+        }
+    }
+}
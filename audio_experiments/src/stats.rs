@@ -0,0 +1,31 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Frame counters of an [`AudioCapturer`](crate::AudioCapturer).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CaptureStats {
+    /// Frames read from the source.
+    pub produced: u64,
+    /// Frames that [`read_frame`](crate::AudioCapturer::read_frame) missed because the consumer
+    /// didn't keep up. Other subscriptions count their own, see
+    /// [`Subscription::dropped`](crate::Subscription::dropped).
+    pub dropped: u64,
+}
+
+/// The live counters behind [`CaptureStats`], shared with the reader thread.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    produced: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn produced(&self) {
+        self.produced.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self, dropped: u64) -> CaptureStats {
+        CaptureStats {
+            produced: self.produced.load(Ordering::Relaxed),
+            dropped,
+        }
+    }
+}
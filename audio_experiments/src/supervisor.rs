@@ -0,0 +1,214 @@
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
+use std::time::{Duration, Instant};
+use crate::format::CaptureConfig;
+use crate::source::{AudioSource, StopHandle};
+use crate::sync::lock;
+use crate::AudioCaptureError;
+
+/// How a [`Supervised`] source backs off between restarts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Delay before the first restart. Doubles on every consecutive failure.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay.
+    pub max_backoff: Duration,
+    /// Give up after this many consecutive failed restarts. `None` keeps trying forever.
+    pub max_restarts: Option<u32>,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(10),
+            max_restarts: None,
+        }
+    }
+}
+
+/// State transitions reported by a [`Supervised`] source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SupervisorState {
+    /// The source is (re)started and delivering audio. `restarts` counts every restart so far.
+    Running { restarts: u32 },
+    /// The source failed. A restart follows, unless the policy says to give up.
+    Failed(String),
+    /// Waiting `delay` before restart attempt number `attempt` (counting consecutive failures).
+    Restarting { attempt: u32, delay: Duration },
+    /// Too many consecutive failures, the source stays down.
+    GaveUp,
+}
+
+type Factory<S> = Box<dyn FnMut() -> Result<S, AudioCaptureError> + Send>;
+type StateListener = Box<dyn FnMut(SupervisorState) + Send>;
+
+/// Keeps a source (typically an [`FfmpegSource`](crate::FfmpegSource)) alive by recreating it
+/// with backoff whenever it fails. Frames keep coming from the same
+/// [`AudioCapturer`](crate::AudioCapturer) across restarts.
+pub struct Supervised<S: AudioSource> {
+    factory: Factory<S>,
+    on_state: StateListener,
+    policy: RestartPolicy,
+    config: CaptureConfig,
+    current: Option<S>,
+    current_stop: Arc<Mutex<Option<StopHandle>>>,
+    stopped: Arc<AtomicBool>,
+    failures: u32,
+    restarts: u32,
+    healthy: bool,
+}
+
+impl<S: AudioSource> Supervised<S> {
+    /// Starts the first source right away, so a broken setup is reported immediately. `factory`
+    /// must produce sources with the same config every time.
+    pub fn new(
+        mut factory: impl FnMut() -> Result<S, AudioCaptureError> + Send + 'static,
+        policy: RestartPolicy,
+        on_state: impl FnMut(SupervisorState) + Send + 'static,
+    ) -> Result<Self, AudioCaptureError> {
+        let source = factory()?;
+        let config = source.config();
+
+        let mut supervised = Supervised {
+            factory: Box::new(factory),
+            on_state: Box::new(on_state),
+            policy,
+            config,
+            current: None,
+            current_stop: Arc::new(Mutex::new(None)),
+            stopped: Arc::new(AtomicBool::new(false)),
+            failures: 0,
+            restarts: 0,
+            healthy: false,
+        };
+        supervised.install(source);
+
+        Ok(supervised)
+    }
+
+    fn install(&mut self, source: S) {
+        *lock(&self.current_stop) = Some(source.stop_handle());
+        self.current = Some(source);
+
+        // stop() may have come in while we were starting, and would have missed this source.
+        if self.stopped.load(Ordering::SeqCst) {
+            if let Some(source) = &self.current {
+                source.stop_handle().stop();
+            }
+        }
+    }
+
+    /// Sleeps for `delay`, waking up early if the source is stopped.
+    fn backoff(&self, delay: Duration) {
+        let deadline = Instant::now() + delay;
+        while !self.stopped.load(Ordering::SeqCst) {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            std::thread::sleep((deadline - now).min(Duration::from_millis(50)));
+        }
+    }
+
+    /// Handles a failure, returning the error to give up with or `None` to keep going.
+    fn fail(&mut self, error: AudioCaptureError) -> Option<AudioCaptureError> {
+        self.current = None;
+        self.healthy = false;
+        (self.on_state)(SupervisorState::Failed(error.to_string()));
+
+        if self.policy.max_restarts.is_some_and(|max| self.failures >= max) {
+            (self.on_state)(SupervisorState::GaveUp);
+            return Some(error);
+        }
+
+        self.failures += 1;
+        let delay = self.policy.initial_backoff
+            .saturating_mul(2u32.saturating_pow(self.failures - 1))
+            .min(self.policy.max_backoff);
+        (self.on_state)(SupervisorState::Restarting { attempt: self.failures, delay });
+        self.backoff(delay);
+
+        None
+    }
+}
+
+impl<S: AudioSource> AudioSource for Supervised<S> {
+    fn config(&self) -> CaptureConfig {
+        self.config
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), AudioCaptureError> {
+        loop {
+            if self.stopped.load(Ordering::SeqCst) {
+                return Err(AudioCaptureError::Stopped);
+            }
+
+            let source = match &mut self.current {
+                Some(source) => source,
+                None => {
+                    match (self.factory)() {
+                        Ok(source) if source.config() != self.config => {
+                            let error = AudioCaptureError::InvalidConfig(format!(
+                                "restarted source has config {:?}, expected {:?}",
+                                source.config(),
+                                self.config
+                            ));
+                            if let Some(error) = self.fail(error) {
+                                return Err(error);
+                            }
+                        }
+                        Ok(source) => {
+                            self.restarts += 1;
+                            self.install(source);
+                        }
+                        Err(error) => {
+                            if let Some(error) = self.fail(error) {
+                                return Err(error);
+                            }
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            match source.read_exact(buf) {
+                Ok(()) => {
+                    if !self.healthy {
+                        // Only count the source as up once audio actually flows.
+                        self.healthy = true;
+                        self.failures = 0;
+                        (self.on_state)(SupervisorState::Running { restarts: self.restarts });
+                    }
+                    return Ok(());
+                }
+                Err(AudioCaptureError::Stopped) if self.stopped.load(Ordering::SeqCst) => {
+                    return Err(AudioCaptureError::Stopped);
+                }
+                Err(error) => {
+                    if let Some(error) = self.fail(error) {
+                        return Err(error);
+                    }
+                }
+            }
+        }
+    }
+
+    fn stop_handle(&self) -> StopHandle {
+        let stopped = Arc::clone(&self.stopped);
+        let current_stop = Arc::clone(&self.current_stop);
+        StopHandle::new(move || {
+            stopped.store(true, Ordering::SeqCst);
+            if let Some(stop) = &*lock(&current_stop) {
+                stop.stop();
+            }
+        })
+    }
+
+    fn is_realtime(&self) -> bool {
+        self.current.as_ref().is_none_or(AudioSource::is_realtime)
+    }
+
+    fn can_interrupt(&self) -> bool {
+        self.current.as_ref().is_none_or(AudioSource::can_interrupt)
+    }
+}
@@ -0,0 +1,12 @@
+use std::sync::{LockResult, Mutex, MutexGuard, PoisonError};
+
+/// Locks `mutex`, even if another thread panicked while holding it. Nothing behind our locks
+/// can be left half-updated by a panic, so there's no reason to give up on it.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    ignore_poison(mutex.lock())
+}
+
+/// The guard from a lock or a condition variable wait, poisoned or not, see [`lock`].
+pub(crate) fn ignore_poison<G>(result: LockResult<G>) -> G {
+    result.unwrap_or_else(PoisonError::into_inner)
+}
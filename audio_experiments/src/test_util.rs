@@ -0,0 +1,18 @@
+use std::path::PathBuf;
+use std::time::Instant;
+use crate::format::Sample;
+use crate::frame::Frame;
+
+/// A path in the temp dir that is unique to this test run.
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("audio_experiments_{}_{}", std::process::id(), name))
+}
+
+/// `len` samples per channel counting up from `start`, stereo with the right channel negated, at
+/// 1 kHz. Where each sample ended up is easy to tell from its value.
+pub(crate) fn counting_frame<T: Sample + From<i16>>(start: u64, len: usize) -> Frame<T> {
+    let samples: Vec<T> = (start..start + len as u64)
+        .flat_map(|i| [T::from(i as i16), T::from(-(i as i16))])
+        .collect();
+    Frame::new(&samples, 2, 1000).with_timing(start, Instant::now())
+}
@@ -0,0 +1,348 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Take, Write};
+use std::path::Path;
+use crate::format::{CaptureConfig, Sample, SampleFormat};
+use crate::frame::Frame;
+use crate::pcm::PcmSource;
+use crate::source::{AudioSource, StopHandle};
+use crate::AudioCaptureError;
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// How fast a [`WavSource`] or [`MediaSource`](crate::MediaSource) hands out audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pacing {
+    /// At the file's sample rate, like a sound card would.
+    RealTime,
+    /// As fast as the consumer reads it. Nothing gets dropped in this mode.
+    Unthrottled,
+}
+
+/// Plays back a PCM WAV file (8/16/24/32-bit integer or 32/64-bit float).
+pub struct WavSource {
+    pcm: PcmSource<Take<BufReader<File>>>,
+}
+
+impl WavSource {
+    pub fn open(path: impl AsRef<Path>, pacing: Pacing) -> Result<Self, AudioCaptureError> {
+        let file = File::open(path).map_err(AudioCaptureError::ReadError)?;
+        let mut reader = BufReader::new(file);
+        let (config, data_len) = read_header(&mut reader)?;
+
+        // Stop at the end of the data chunk, chunks after it aren't audio.
+        let pcm = PcmSource::new(reader.take(data_len), config).paced(pacing);
+        Ok(WavSource { pcm })
+    }
+}
+
+impl AudioSource for WavSource {
+    fn config(&self) -> CaptureConfig {
+        self.pcm.config()
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), AudioCaptureError> {
+        self.pcm.read_exact(buf)
+    }
+
+    fn stop_handle(&self) -> StopHandle {
+        self.pcm.stop_handle()
+    }
+
+    fn is_realtime(&self) -> bool {
+        self.pcm.is_realtime()
+    }
+}
+
+/// Reads the RIFF header up to the start of the `data` chunk, returning the format and the length
+/// of the sample data in bytes.
+pub(crate) fn read_header(reader: &mut (impl Read + Seek)) -> Result<(CaptureConfig, u64), AudioCaptureError> {
+    let mut riff = [0u8; 12];
+    reader.read_exact(&mut riff).map_err(AudioCaptureError::ReadError)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err(AudioCaptureError::InvalidWav("missing RIFF/WAVE header".into()));
+    }
+
+    let mut config = None;
+    loop {
+        let mut chunk_header = [0u8; 8];
+        reader.read_exact(&mut chunk_header).map_err(|_| {
+            AudioCaptureError::InvalidWav("no data chunk".into())
+        })?;
+        let id = &chunk_header[0..4];
+        let len = u32::from_le_bytes(chunk_header[4..8].try_into().unwrap()) as u64;
+
+        match id {
+            b"fmt " => {
+                if len < 16 {
+                    return Err(AudioCaptureError::InvalidWav("fmt chunk too short".into()));
+                }
+                let mut fmt = vec![0u8; len as usize];
+                reader.read_exact(&mut fmt).map_err(AudioCaptureError::ReadError)?;
+                // Odd-sized chunks are followed by a pad byte.
+                skip(reader, len & 1)?;
+
+                config = Some(parse_fmt(&fmt)?);
+            }
+            b"data" => {
+                let config = config.ok_or_else(|| {
+                    AudioCaptureError::InvalidWav("data chunk before fmt chunk".into())
+                })?;
+                return Ok((config, len));
+            }
+            _ => skip(reader, len + (len & 1))?,
+        }
+    }
+}
+
+fn parse_fmt(fmt: &[u8]) -> Result<CaptureConfig, AudioCaptureError> {
+    let mut format_tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+    let channels = u16::from_le_bytes([fmt[2], fmt[3]]);
+    let sample_rate = u32::from_le_bytes(fmt[4..8].try_into().unwrap());
+    let bits_per_sample = u16::from_le_bytes([fmt[14], fmt[15]]);
+
+    // WAVE_FORMAT_EXTENSIBLE keeps the real format tag in the first two bytes of the sub format
+    // GUID.
+    if format_tag == WAVE_FORMAT_EXTENSIBLE {
+        if fmt.len() < 26 {
+            return Err(AudioCaptureError::InvalidWav("extensible fmt chunk too short".into()));
+        }
+        format_tag = u16::from_le_bytes([fmt[24], fmt[25]]);
+    }
+
+    let format = match (format_tag, bits_per_sample) {
+        (WAVE_FORMAT_PCM, 8) => SampleFormat::U8,
+        (WAVE_FORMAT_PCM, 16) => SampleFormat::S16,
+        (WAVE_FORMAT_PCM, 24) => SampleFormat::S24,
+        (WAVE_FORMAT_PCM, 32) => SampleFormat::S32,
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => SampleFormat::F32,
+        (WAVE_FORMAT_IEEE_FLOAT, 64) => SampleFormat::F64,
+        _ => {
+            return Err(AudioCaptureError::InvalidWav(format!(
+                "unsupported format (tag {:#06x}, {} bits)",
+                format_tag, bits_per_sample
+            )))
+        }
+    };
+
+    if channels == 0 || sample_rate == 0 {
+        return Err(AudioCaptureError::InvalidWav("zero channels or sample rate".into()));
+    }
+
+    Ok(CaptureConfig {
+        sample_rate,
+        channels,
+        format,
+    })
+}
+
+fn skip(reader: &mut impl Seek, len: u64) -> Result<(), AudioCaptureError> {
+    reader.seek(SeekFrom::Current(len as i64)).map_err(AudioCaptureError::ReadError)?;
+    Ok(())
+}
+
+/// Writes frames to a WAV file. The header gets its final sizes on [`finish`](Self::finish), or
+/// when the writer is dropped.
+pub struct WavWriter {
+    writer: BufWriter<File>,
+    config: CaptureConfig,
+    data_len: u64,
+    encode_buf: Vec<u8>,
+    finished: bool,
+}
+
+impl WavWriter {
+    /// Creates (or truncates) `path`. Samples are stored in `config.format`, and frames must match
+    /// `config`'s channel count.
+    pub fn create(path: impl AsRef<Path>, config: CaptureConfig) -> Result<Self, AudioCaptureError> {
+        let file = File::create(path).map_err(AudioCaptureError::RecordError)?;
+        let mut writer = WavWriter {
+            writer: BufWriter::new(file),
+            config,
+            data_len: 0,
+            encode_buf: Vec::new(),
+            finished: false,
+        };
+        // Sizes are filled in once we know them.
+        writer.write_header().map_err(AudioCaptureError::RecordError)?;
+        Ok(writer)
+    }
+
+    pub fn config(&self) -> CaptureConfig {
+        self.config
+    }
+
+    /// How much audio has been written so far.
+    pub fn duration(&self) -> std::time::Duration {
+        let frames = self.data_len / self.config.bytes_per_frame() as u64;
+        std::time::Duration::from_secs_f64(frames as f64 / self.config.sample_rate as f64)
+    }
+
+    pub fn write_frame<T: Sample>(&mut self, frame: &Frame<T>) -> Result<(), AudioCaptureError> {
+        if frame.channels() != self.config.channels {
+            return Err(AudioCaptureError::InvalidConfig(format!(
+                "frame has {} channels, recording has {}",
+                frame.channels(),
+                self.config.channels
+            )));
+        }
+
+        let format = self.config.format;
+        self.encode_buf.resize(frame.len() * self.config.bytes_per_frame(), 0);
+        for (sample, out) in frame.interleaved().zip(self.encode_buf.chunks_exact_mut(format.bytes_per_sample())) {
+            format.encode(sample.to_f32(), out);
+        }
+
+        self.writer.write_all(&self.encode_buf).map_err(AudioCaptureError::RecordError)?;
+        self.data_len += self.encode_buf.len() as u64;
+        Ok(())
+    }
+
+    /// Fills in the header sizes and flushes everything to disk.
+    pub fn finish(mut self) -> Result<(), AudioCaptureError> {
+        self.finalize().map_err(AudioCaptureError::RecordError)
+    }
+
+    fn finalize(&mut self) -> std::io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        // Odd-sized chunks are followed by a pad byte.
+        if self.data_len % 2 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        let format = self.config.format;
+        let (format_tag, bits) = match format {
+            SampleFormat::U8 => (WAVE_FORMAT_PCM, 8u16),
+            SampleFormat::S16 => (WAVE_FORMAT_PCM, 16),
+            SampleFormat::S24 => (WAVE_FORMAT_PCM, 24),
+            SampleFormat::S32 => (WAVE_FORMAT_PCM, 32),
+            SampleFormat::F32 => (WAVE_FORMAT_IEEE_FLOAT, 32),
+            SampleFormat::F64 => (WAVE_FORMAT_IEEE_FLOAT, 64),
+        };
+        let block_align = self.config.bytes_per_frame() as u16;
+        // The sizes are 32 bits, so a recording that outgrows 4GB gets a saturated header.
+        let data_len = self.data_len.min(u32::MAX as u64 - 36) as u32;
+        let riff_len = 36 + data_len + (data_len & 1);
+
+        let w = &mut self.writer;
+        w.write_all(b"RIFF")?;
+        w.write_all(&riff_len.to_le_bytes())?;
+        w.write_all(b"WAVEfmt ")?;
+        w.write_all(&16u32.to_le_bytes())?;
+        w.write_all(&format_tag.to_le_bytes())?;
+        w.write_all(&self.config.channels.to_le_bytes())?;
+        w.write_all(&self.config.sample_rate.to_le_bytes())?;
+        w.write_all(&(self.config.sample_rate * block_align as u32).to_le_bytes())?;
+        w.write_all(&block_align.to_le_bytes())?;
+        w.write_all(&bits.to_le_bytes())?;
+        w.write_all(b"data")?;
+        w.write_all(&data_len.to_le_bytes())
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        let _ = self.finalize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    /// Writes a minimal WAV file to the temp dir and returns its path.
+    fn write_wav(name: &str, format_tag: u16, bits: u16, channels: u16, data: &[u8]) -> std::path::PathBuf {
+        let block_align = channels * bits / 8;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&format_tag.to_le_bytes());
+        bytes.extend_from_slice(&channels.to_le_bytes());
+        bytes.extend_from_slice(&8000u32.to_le_bytes());
+        bytes.extend_from_slice(&(8000 * block_align as u32).to_le_bytes());
+        bytes.extend_from_slice(&block_align.to_le_bytes());
+        bytes.extend_from_slice(&bits.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+
+        let path = temp_path(&format!("{}.wav", name));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn read_samples(path: &Path, count: usize) -> Vec<i16> {
+        let mut source = WavSource::open(path, Pacing::Unthrottled).unwrap();
+        let format = source.config().format;
+        let mut buf = vec![0u8; count * format.bytes_per_sample()];
+        source.read_exact(&mut buf).unwrap();
+        buf.chunks_exact(format.bytes_per_sample())
+            .map(|raw| i16::decode(format, raw))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_every_supported_format() {
+        let expected = [0, 16384, -16384, -32768];
+
+        let u8_data = [128u8, 192, 64, 0];
+        let s24_data: Vec<u8> = [0i32, 0x40_0000, -0x40_0000, -0x80_0000].iter()
+            .flat_map(|v| v.to_le_bytes()[..3].to_vec())
+            .collect();
+        let s32_data: Vec<u8> = [0i32, 0x4000_0000, -0x4000_0000, i32::MIN].iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let f32_data: Vec<u8> = [0.0f32, 0.5, -0.5, -1.0].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let f64_data: Vec<u8> = [0.0f64, 0.5, -0.5, -1.0].iter().flat_map(|v| v.to_le_bytes()).collect();
+
+        let cases: [(&str, u16, u16, &[u8]); 5] = [
+            ("u8", WAVE_FORMAT_PCM, 8, &u8_data),
+            ("s24", WAVE_FORMAT_PCM, 24, &s24_data),
+            ("s32", WAVE_FORMAT_PCM, 32, &s32_data),
+            ("f32", WAVE_FORMAT_IEEE_FLOAT, 32, &f32_data),
+            ("f64", WAVE_FORMAT_IEEE_FLOAT, 64, &f64_data),
+        ];
+
+        for (name, tag, bits, data) in cases {
+            let path = write_wav(name, tag, bits, 2, data);
+            assert_eq!(read_samples(&path, 4), expected, "{}", name);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn writer_round_trip() {
+        let path = temp_path("writer.wav");
+        let config = CaptureConfig { sample_rate: 8000, channels: 2, format: SampleFormat::S24 };
+        let frame = Frame::new(&[0.5f32, -0.5, 0.25, -1.0, 0.0, 0.75], 2, 8000);
+
+        let mut writer = WavWriter::create(&path, config).unwrap();
+        writer.write_frame(&frame).unwrap();
+        // Dropping finalizes the header just like finish() does.
+        drop(writer);
+
+        let mut source = WavSource::open(&path, Pacing::Unthrottled).unwrap();
+        assert_eq!(source.config(), config);
+        let mut buf = vec![0u8; 6 * 3];
+        source.read_exact(&mut buf).unwrap();
+        let samples: Vec<f32> = buf.chunks_exact(3).map(|raw| f32::decode(config.format, raw)).collect();
+        assert_eq!(samples, frame.interleaved().collect::<Vec<_>>());
+        assert!(matches!(source.read_exact(&mut buf), Err(AudioCaptureError::EndOfStream)));
+
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,181 @@
+use std::sync::Arc;
+use std::time::Instant;
+use crate::broadcast::Subscription;
+use crate::format::Sample;
+use crate::frame::Frame;
+use crate::pool::BufferPool;
+use crate::AudioCaptureError;
+
+/// Cuts a stream of frames into overlapping windows of `window` samples per channel, starting a
+/// new one every `hop` samples. With a 4096 window and a hop of 512, an FFT gets its full
+/// resolution but sees new audio every 512 samples.
+///
+/// The stream has to be continuous. When frames go missing (according to
+/// [`Frame::sample_index`]), the windower starts over after the gap instead of stitching audio
+/// from both sides together.
+pub struct Windower<T: Sample> {
+    window: usize,
+    hop: usize,
+    /// Planar samples not yet consumed by the hop, one Vec per channel.
+    history: Vec<Vec<T>>,
+    sample_rate: u32,
+    /// Index of the first sample in `history`.
+    start_index: u64,
+    captured_at: Option<Instant>,
+    pool: Arc<BufferPool<T>>,
+}
+
+impl<T: Sample> Windower<T> {
+    /// `hop` has to be between 1 and `window`.
+    pub fn new(window: usize, hop: usize) -> Result<Self, AudioCaptureError> {
+        if window == 0 || hop == 0 || hop > window {
+            return Err(AudioCaptureError::InvalidConfig(format!("window {} with hop {}", window, hop)));
+        }
+
+        Ok(Windower {
+            window,
+            hop,
+            history: Vec::new(),
+            sample_rate: 0,
+            start_index: 0,
+            captured_at: None,
+            pool: BufferPool::new(),
+        })
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    pub fn hop(&self) -> usize {
+        self.hop
+    }
+
+    /// Adds a frame to the stream. Take the windows it completes with
+    /// [`next_window`](Self::next_window).
+    pub fn push(&mut self, frame: &Frame<T>) {
+        let expected = self.start_index + self.history.first().map_or(0, Vec::len) as u64;
+        let continuous = frame.channels() as usize == self.history.len()
+            && frame.sample_rate() == self.sample_rate
+            && frame.sample_index() == expected;
+        if !continuous {
+            self.history.resize_with(frame.channels() as usize, Vec::new);
+            for channel in &mut self.history {
+                channel.clear();
+            }
+            self.sample_rate = frame.sample_rate();
+            self.start_index = frame.sample_index();
+        }
+
+        for (c, channel) in self.history.iter_mut().enumerate() {
+            channel.extend_from_slice(frame.channel(c));
+        }
+        self.captured_at = Some(frame.captured_at());
+    }
+
+    /// The next complete window, if the frames pushed so far make one. Its `captured_at` is when
+    /// its newest audio came in.
+    pub fn next_window(&mut self) -> Option<Frame<T>> {
+        let captured_at = self.captured_at?;
+        if self.history.first().is_none_or(|channel| channel.len() < self.window) {
+            return None;
+        }
+
+        let mut planar = self.pool.get(self.window * self.history.len());
+        for (c, channel) in self.history.iter_mut().enumerate() {
+            planar[c * self.window..(c + 1) * self.window].copy_from_slice(&channel[..self.window]);
+            channel.drain(..self.hop);
+        }
+        let window = Frame::from_buffer(planar, self.history.len() as u16, self.sample_rate)
+            .with_timing(self.start_index, captured_at);
+        self.start_index += self.hop as u64;
+
+        Some(window)
+    }
+}
+
+/// Overlapping windows of everything an [`AudioCapturer`](crate::AudioCapturer) captures, see
+/// [`AudioCapturer::windows`](crate::AudioCapturer::windows).
+pub struct Windows<T: Sample> {
+    frames: Subscription<Frame<T>>,
+    windower: Windower<T>,
+}
+
+impl<T: Sample> Windows<T> {
+    pub(crate) fn new(frames: Subscription<Frame<T>>, windower: Windower<T>) -> Self {
+        Windows { frames, windower }
+    }
+
+    /// Blocks until the next window is complete. `None` once capturing has ended.
+    pub fn next_window(&mut self) -> Option<Frame<T>> {
+        loop {
+            if let Some(window) = self.windower.next_window() {
+                return Some(window);
+            }
+            let frame = self.frames.recv().ok()?;
+            self.windower.push(&frame);
+        }
+    }
+
+    /// Like [`next_window`](Self::next_window), but returns `None` right away if no window is
+    /// complete yet.
+    pub fn try_next_window(&mut self) -> Option<Frame<T>> {
+        loop {
+            if let Some(window) = self.windower.next_window() {
+                return Some(window);
+            }
+            let frame = self.frames.try_recv().ok()?;
+            self.windower.push(&frame);
+        }
+    }
+
+    /// How many frames were lost because windows weren't taken fast enough.
+    pub fn dropped(&self) -> u64 {
+        self.frames.dropped()
+    }
+}
+
+impl<T: Sample> Iterator for Windows<T> {
+    type Item = Frame<T>;
+
+    fn next(&mut self) -> Option<Frame<T>> {
+        self.next_window()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::counting_frame;
+
+    #[test]
+    fn overlapping_windows() {
+        let mut windower = Windower::<i16>::new(8, 3).unwrap();
+        let frame = |start| counting_frame::<i16>(start, 5);
+
+        windower.push(&frame(0));
+        assert!(windower.next_window().is_none());
+        windower.push(&frame(5));
+        windower.push(&frame(10));
+
+        let first = windower.next_window().unwrap();
+        assert_eq!(first.left(), &[0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(first.right(), &[0, -1, -2, -3, -4, -5, -6, -7]);
+        assert_eq!(first.sample_index(), 0);
+        let second = windower.next_window().unwrap();
+        assert_eq!(second.left(), &[3, 4, 5, 6, 7, 8, 9, 10]);
+        assert_eq!(second.sample_index(), 3);
+        let third = windower.next_window().unwrap();
+        assert_eq!(third.left()[0], 6);
+        assert!(windower.next_window().is_none());
+
+        // After a gap, windows start over from the new audio.
+        windower.push(&frame(100));
+        windower.push(&frame(105));
+        let after_gap = windower.next_window().unwrap();
+        assert_eq!(after_gap.left(), &[100, 101, 102, 103, 104, 105, 106, 107]);
+        assert_eq!(after_gap.sample_index(), 100);
+
+        assert!(Windower::<i16>::new(512, 4096).is_err());
+    }
+}
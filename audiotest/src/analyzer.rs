@@ -0,0 +1,268 @@
+use spectrum_analyzer::error::SpectrumAnalyzerError;
+use spectrum_analyzer::scaling::SpectrumDataStats;
+use spectrum_analyzer::{samples_fft_to_spectrum, FrequencyLimit, FrequencySpectrum};
+use std::f32::consts::PI;
+use thiserror::Error;
+
+/// The largest FFT spectrum-analyzer can compute.
+const MAX_FFT_SIZE: usize = 16384;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("FFT size {0} is not a power of two")]
+    FftSize(usize),
+
+    #[error("Zero-padding factor {0} is not a power of two")]
+    ZeroPadding(usize),
+
+    #[error("Padded FFT size {0} is larger than {max}", max = MAX_FFT_SIZE)]
+    TooLarge(usize),
+
+    #[error("Frequency limit {limit:?} is outside 0..={nyquist} Hz")]
+    FrequencyLimit { limit: FrequencyLimit, nyquist: f32 },
+}
+
+/// Window applied to the samples before the FFT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    /// No window at all. Sharpest peaks, but the most leakage.
+    Rectangular,
+    Hann,
+    Hamming,
+    /// 4-term Blackman-Harris. Very little leakage, at the cost of wide peaks.
+    BlackmanHarris,
+    /// Widest peaks of all, but their height is accurate wherever the frequency falls between
+    /// bins. Good for measuring levels.
+    FlatTop,
+}
+
+impl Window {
+    /// Coefficients of the window as a sum of cosines.
+    fn coefficients(self) -> &'static [f32] {
+        match self {
+            Window::Rectangular => &[1.0],
+            Window::Hann => &[0.5, 0.5],
+            Window::Hamming => &[0.54, 0.46],
+            Window::BlackmanHarris => &[0.35875, 0.48829, 0.14128, 0.01168],
+            Window::FlatTop => &[0.21557895, 0.41663158, 0.277263158, 0.083578947, 0.006947368],
+        }
+    }
+
+    /// The window over `len` samples.
+    pub fn values(self, len: usize) -> Vec<f32> {
+        let coefficients = self.coefficients();
+        let span = len.saturating_sub(1).max(1) as f32;
+        (0..len)
+            .map(|i| {
+                let phase = 2.0 * PI * i as f32 / span;
+                coefficients
+                    .iter()
+                    .enumerate()
+                    .map(|(k, &a)| {
+                        let a = if k % 2 == 0 { a } else { -a };
+                        a * (k as f32 * phase).cos()
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+/// How the spectrum is computed.
+#[derive(Debug, Clone, Copy)]
+pub struct AnalyzerConfig {
+    pub window: Window,
+    /// Samples per FFT. Has to be a power of two.
+    pub fft_size: usize,
+    /// The windowed samples are padded with zeros to `fft_size * zero_padding` before the FFT,
+    /// for finer frequency steps between the bins. Has to be a power of two; 1 pads nothing.
+    pub zero_padding: usize,
+    /// Which frequencies end up in the spectrum. The default keeps everything from 20 Hz up to
+    /// the Nyquist frequency, so it works at any sample rate.
+    pub frequency_limit: FrequencyLimit,
+}
+
+impl Default for AnalyzerConfig {
+    fn default() -> Self {
+        AnalyzerConfig {
+            window: Window::Hann,
+            fft_size: 4096,
+            zero_padding: 1,
+            frequency_limit: FrequencyLimit::Min(20.0),
+        }
+    }
+}
+
+impl AnalyzerConfig {
+    /// Checks the config works for audio at `sample_rate`.
+    pub fn validate(&self, sample_rate: u32) -> Result<(), ConfigError> {
+        if !self.fft_size.is_power_of_two() || self.fft_size < 2 {
+            return Err(ConfigError::FftSize(self.fft_size));
+        }
+        if !self.zero_padding.is_power_of_two() {
+            return Err(ConfigError::ZeroPadding(self.zero_padding));
+        }
+        let padded = self.fft_size.saturating_mul(self.zero_padding);
+        if padded > MAX_FFT_SIZE {
+            return Err(ConfigError::TooLarge(padded));
+        }
+
+        let nyquist = sample_rate as f32 / 2.0;
+        let in_range = |frequency: f32| (0.0..=nyquist).contains(&frequency);
+        let valid = match self.frequency_limit {
+            FrequencyLimit::All => true,
+            FrequencyLimit::Min(min) => in_range(min),
+            FrequencyLimit::Max(max) => in_range(max),
+            FrequencyLimit::Range(min, max) => in_range(min) && in_range(max) && min <= max,
+        };
+        if !valid {
+            return Err(ConfigError::FrequencyLimit {
+                limit: self.frequency_limit,
+                nyquist,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Computes spectra of mono audio at one sample rate.
+pub struct Analyzer {
+    config: AnalyzerConfig,
+    sample_rate: u32,
+    window: Vec<f32>,
+    /// Brings a full-scale sine to 1.0, whatever the window and padding.
+    scale: f32,
+}
+
+impl Analyzer {
+    pub fn new(config: AnalyzerConfig, sample_rate: u32) -> Result<Analyzer, ConfigError> {
+        config.validate(sample_rate)?;
+
+        let window = config.window.values(config.fft_size);
+        // A sine of amplitude A ends up as A / 2 * sum(window) in its bin.
+        let scale = 2.0 / window.iter().sum::<f32>();
+        Ok(Analyzer {
+            config,
+            sample_rate,
+            window,
+            scale,
+        })
+    }
+
+    pub fn config(&self) -> AnalyzerConfig {
+        self.config
+    }
+
+    /// Spectrum of the last `fft_size` of the normalized mono `samples`. Fewer samples than that
+    /// are padded with silence.
+    pub fn analyze(&self, samples: &[f32]) -> Result<FrequencySpectrum, SpectrumAnalyzerError> {
+        let samples = &samples[samples.len().saturating_sub(self.config.fft_size)..];
+        let mut windowed = vec![0.0; self.config.fft_size * self.config.zero_padding];
+        for ((out, sample), window) in windowed.iter_mut().zip(samples).zip(&self.window) {
+            *out = sample * window;
+        }
+
+        let scale = self.scale;
+        let scaling = move |value: f32, _: &SpectrumDataStats| value * scale;
+        samples_fft_to_spectrum(
+            &windowed,
+            self.sample_rate,
+            self.config.frequency_limit,
+            Some(&scaling),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_spectrum::to_mono;
+
+    /// `len` frames of a full-scale stereo sine, as the recorder would deliver it.
+    fn stereo_sine(frequency: f32, sample_rate: u32, len: usize) -> Vec<i32> {
+        (0..len)
+            .flat_map(|i| {
+                let sample = (2.0 * PI * frequency * i as f32 / sample_rate as f32).sin();
+                let sample = (sample as f64 * i32::MAX as f64) as i32;
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn peak_lands_on_the_sine() {
+        for sample_rate in [44100, 48000] {
+            for frequency in [100.0, 1000.0, 5000.0] {
+                let samples: Vec<f32> = to_mono(&stereo_sine(frequency, sample_rate, 4096), 2).collect();
+                let analyzer = Analyzer::new(AnalyzerConfig::default(), sample_rate).unwrap();
+                let spectrum = analyzer.analyze(&samples).unwrap();
+
+                let (peak, magnitude) = spectrum.max();
+                assert!(
+                    (peak.val() - frequency).abs() <= spectrum.frequency_resolution(),
+                    "{} Hz sine at {} Hz peaks at {} Hz",
+                    frequency,
+                    sample_rate,
+                    peak.val()
+                );
+                // Off-bin frequencies lose up to 1.4 dB through a Hann window.
+                assert!((magnitude.val() - 1.0).abs() < 0.16, "magnitude {}", magnitude.val());
+            }
+        }
+    }
+
+    #[test]
+    fn windows_and_padding() {
+        let samples: Vec<f32> = to_mono(&stereo_sine(1000.0, 48000, 1024), 2).collect();
+        for window in [
+            Window::Rectangular,
+            Window::Hann,
+            Window::Hamming,
+            Window::BlackmanHarris,
+            Window::FlatTop,
+        ] {
+            let config = AnalyzerConfig {
+                window,
+                fft_size: 1024,
+                zero_padding: 4,
+                frequency_limit: FrequencyLimit::Range(100.0, 10000.0),
+            };
+            let spectrum = Analyzer::new(config, 48000).unwrap().analyze(&samples).unwrap();
+
+            // Padding makes the steps between bins four times as fine.
+            assert!((spectrum.frequency_resolution() - 48000.0 / 4096.0).abs() < 1e-3);
+            let (peak, magnitude) = spectrum.max();
+            assert!((peak.val() - 1000.0).abs() <= spectrum.frequency_resolution(), "{:?}", window);
+            if window == Window::FlatTop {
+                assert!((magnitude.val() - 1.0).abs() < 0.01, "magnitude {}", magnitude.val());
+            }
+            assert!(spectrum.data().first().unwrap().0.val() >= 100.0);
+            assert!(spectrum.data().last().unwrap().0.val() <= 10000.0);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_configs() {
+        let config = AnalyzerConfig::default();
+        assert!(matches!(
+            AnalyzerConfig { fft_size: 1000, ..config }.validate(48000),
+            Err(ConfigError::FftSize(1000))
+        ));
+        assert!(matches!(
+            AnalyzerConfig { zero_padding: 3, ..config }.validate(48000),
+            Err(ConfigError::ZeroPadding(3))
+        ));
+        assert!(matches!(
+            AnalyzerConfig { fft_size: 8192, zero_padding: 4, ..config }.validate(48000),
+            Err(ConfigError::TooLarge(32768))
+        ));
+        assert!(AnalyzerConfig { fft_size: 4096, zero_padding: 4, ..config }.validate(48000).is_ok());
+        assert!(matches!(
+            AnalyzerConfig { frequency_limit: FrequencyLimit::Max(30000.0), ..config }.validate(48000),
+            Err(ConfigError::FrequencyLimit { .. })
+        ));
+        for sample_rate in [8000, 16000, 44100, 48000] {
+            assert!(config.validate(sample_rate).is_ok());
+        }
+    }
+}
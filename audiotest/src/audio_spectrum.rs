@@ -1,115 +1,257 @@
+use crate::analyzer::{Analyzer, AnalyzerConfig, ConfigError};
 use ringbuffer::{AllocRingBuffer, RingBuffer, RingBufferExt};
 use simple_pulse_desktop_capture::DesktopAudioRecorder;
+use spectrum_analyzer::error::SpectrumAnalyzerError;
 use spectrum_analyzer::FrequencySpectrum;
+use crate::latest::{latest, LatestReader};
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
 
-// Record samples
-// Throw last 4096 samples into FFT
-// Send fft result through channel to be received by different thread
+/// What the recorder delivers: interleaved frames of `channels` samples, `sample_rate` frames a
+/// second. DesktopAudioRecorder doesn't report its stream format, so this has to match what it
+/// asks PulseAudio for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl Default for AudioFormat {
+    /// 44.1 kHz stereo, as DesktopAudioRecorder records.
+    fn default() -> Self {
+        AudioFormat {
+            sample_rate: 44100,
+            channels: 2,
+        }
+    }
+}
+
+/// A sample format that can be brought to the -1.0..=1.0 range the FFT expects.
+pub trait Sample: Copy {
+    fn to_f32(self) -> f32;
+}
+
+impl Sample for i16 {
+    fn to_f32(self) -> f32 {
+        self as f32 / 32768.0
+    }
+}
+
+impl Sample for i32 {
+    fn to_f32(self) -> f32 {
+        self as f32 / 2147483648.0
+    }
+}
+
+impl Sample for f32 {
+    fn to_f32(self) -> f32 {
+        self
+    }
+}
+
+/// Averages each frame of interleaved `samples` down to one normalized sample.
+pub fn to_mono<T: Sample>(samples: &[T], channels: u16) -> impl Iterator<Item = f32> + '_ {
+    let channels = channels.max(1) as usize;
+    samples
+        .chunks_exact(channels)
+        .map(move |frame| frame.iter().map(|sample| sample.to_f32()).sum::<f32>() / channels as f32)
+}
+
+#[derive(Error, Debug)]
+pub enum CaptureError {
+    #[error("Failed to start recording: {0}")]
+    RecorderStart(String),
+
+    #[error("Failed to read audio: {0}")]
+    Read(String),
+
+    #[error("Invalid analyzer config: {0}")]
+    Config(#[from] ConfigError),
+
+    #[error("FFT failed: {0:?}")]
+    Fft(SpectrumAnalyzerError),
+
+    #[error("The capture thread panicked")]
+    ThreadPanicked,
+
+    #[error("Capturing was stopped")]
+    Stopped,
+}
+
+/// One spectrum of the desktop audio.
+pub struct Analysis {
+    pub spectrum: FrequencySpectrum,
+    /// Counts up by one with every spectrum, starting at 1, so readers can tell a new one from
+    /// one they have already seen.
+    pub sequence: u64,
+    /// When the newest samples in the spectrum were read.
+    pub captured_at: Instant,
+}
+
+impl Analysis {
+    pub fn age(&self) -> Duration {
+        self.captured_at.elapsed()
+    }
+}
+
+/// Whatever ended the capture thread. `failed` is set after `error`, so the reader only needs
+/// the lock once something went wrong.
+#[derive(Default)]
+struct Failure {
+    failed: AtomicBool,
+    error: Mutex<Option<CaptureError>>,
+}
+
+impl Failure {
+    fn set(&self, error: CaptureError) {
+        *self.error.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(error);
+        self.failed.store(true, Ordering::Release);
+    }
+}
+
+// Record samples, downmixed to mono
+// Throw last fft_size samples into FFT after every frame
+// Publish the result to the latest spectrum cell, to be read by a different thread whenever
 pub struct Capturer {
-    do_capture: AtomicBool,
-    capture_thread: thread::JoinHandle<()>,
-    spectrum_receiver: Receiver<FrequencySpectrum>,
-    ready_sender: Sender<()>,
+    do_capture: Arc<AtomicBool>,
+    capture_thread: Option<thread::JoinHandle<()>>,
+    latest: LatestReader<Option<Analysis>>,
+    failure: Arc<Failure>,
+    format: AudioFormat,
 }
 
 impl Capturer {
-    pub fn new(application_name: String, bufsize: usize) -> Capturer {
+    /// Starts recording the desktop audio, which comes in as `format`, and analyzing it as
+    /// `config` says. Returns once the recorder is up, or with the reason it couldn't be started.
+    pub fn new(
+        application_name: String,
+        config: AnalyzerConfig,
+        format: AudioFormat,
+    ) -> Result<Capturer, CaptureError> {
+        let analyzer = Analyzer::new(config, format.sample_rate)?;
         let do_capture = Arc::new(AtomicBool::new(true));
-        let (spectrum_sender, spectrum_receiver) = std::sync::mpsc::channel();
-        let (ready_sender, ready_receiver) = std::sync::mpsc::channel::<()>();
+        let failure = Arc::new(Failure::default());
+        let (mut latest_writer, latest) = latest::<Option<Analysis>>();
+        let (started_sender, started_receiver) = std::sync::mpsc::channel();
 
         let capture_thread;
         {
             let do_capture = Arc::clone(&do_capture);
+            let failure = Arc::clone(&failure);
             capture_thread = thread::spawn(move || {
-                let mut buffer = AllocRingBuffer::with_capacity(bufsize);
+                let mut buffer = AllocRingBuffer::with_capacity(config.fft_size);
                 let mut recorder = match DesktopAudioRecorder::new(&application_name) {
                     Ok(recorder) => recorder,
                     Err(e) => {
-                        eprintln!("Recorder thread stopped: {}", e);
+                        let _ = started_sender.send(Err(CaptureError::RecorderStart(e.to_string())));
                         return;
                     }
                 };
+                let _ = started_sender.send(Ok(()));
 
-                let mut is_consumer_ready = false;
+                let mut sequence = 0;
 
-                // Quits when do_capture is false.
+                // Quits when do_capture is false, or when anything goes wrong. The error is
+                // left for the consumer to pick up.
                 while do_capture.load(Ordering::SeqCst) {
                     let frame = match recorder.read_frame() {
                         Ok(f) => f,
                         Err(e) => {
-                            eprintln!("Failed to receive frame: {}", e);
-                            continue;
+                            failure.set(CaptureError::Read(e.to_string()));
+                            return;
                         }
                     };
+                    let captured_at = Instant::now();
 
-                    buffer.extend(frame);
-                    let ready_recv_result = ready_receiver.try_recv();
-
-                    match ready_recv_result {
-                        Ok(..) => is_consumer_ready = true,
-                        Err(..) => {}
-                    }
+                    buffer.extend(to_mono(&frame, format.channels));
 
-                    if buffer.is_full() && is_consumer_ready {
-                        // Do FFT
-                        // TODO: Error handling
-                        let spectrum = fft(&buffer.to_vec()).unwrap();
-                        is_consumer_ready = false;
-                        let _ = spectrum_sender.send(spectrum);
+                    if buffer.is_full() {
+                        let spectrum = match analyzer.analyze(&buffer.to_vec()) {
+                            Ok(spectrum) => spectrum,
+                            Err(e) => {
+                                failure.set(CaptureError::Fft(e));
+                                return;
+                            }
+                        };
+                        sequence += 1;
+                        latest_writer.write(Some(Analysis {
+                            spectrum,
+                            sequence,
+                            captured_at,
+                        }));
                     }
                 }
             });
         }
 
-        let _ = ready_sender.send(());
-
-        Capturer {
-            do_capture: AtomicBool::new(false),
-            capture_thread,
-            spectrum_receiver,
-            ready_sender,
+        match started_receiver.recv() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                let _ = capture_thread.join();
+                return Err(e);
+            }
+            Err(..) => {
+                let _ = capture_thread.join();
+                return Err(CaptureError::ThreadPanicked);
+            }
         }
+
+        Ok(Capturer {
+            do_capture,
+            capture_thread: Some(capture_thread),
+            latest,
+            failure,
+            format,
+        })
     }
 
-    pub fn get_spectrum(&self) -> FrequencySpectrum {
-        let spectrum = self.spectrum_receiver.recv().unwrap();
-        let _ = self.ready_sender.send(());
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// The newest spectrum, or `None` until the first one is ready. Never blocks, so it can be
+    /// called at whatever rate suits the caller; check [`Analysis::sequence`] to tell whether
+    /// the spectrum changed since last time. Fails with whatever ended capturing, and with
+    /// [`CaptureError::Stopped`] after that.
+    pub fn latest_spectrum(&mut self) -> Result<Option<&Analysis>, CaptureError> {
+        if self.failure.failed.load(Ordering::Acquire) {
+            let error = self.failure.error.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+            return Err(error.unwrap_or(CaptureError::Stopped));
+        }
+        Ok(self.latest.read().as_ref())
+    }
 
-        spectrum
+    /// Stops capturing and waits for the capture thread to finish. Calling it again does nothing.
+    pub fn stop(&mut self) -> Result<(), CaptureError> {
+        self.do_capture.store(false, Ordering::SeqCst);
+        match self.capture_thread.take().map(|thread| thread.join()) {
+            Some(Err(..)) => Err(CaptureError::ThreadPanicked),
+            _ => Ok(()),
+        }
     }
 }
 
 impl Drop for Capturer {
     fn drop(&mut self) {
-        self.do_capture.store(false, Ordering::SeqCst);
+        let _ = self.stop();
     }
 }
 
-fn fft(
-    samples: &Vec<i32>,
-) -> Result<FrequencySpectrum, spectrum_analyzer::error::SpectrumAnalyzerError> {
-    use spectrum_analyzer::scaling::divide_by_N;
-    use spectrum_analyzer::windows::hann_window;
-    use spectrum_analyzer::{samples_fft_to_spectrum, FrequencyLimit};
-
-    // Convert samples into f32
-    let f32_samples: Vec<f32> = samples
-        .iter()
-        .map(|sample| (*sample as f32) / (u32::max_value() as f32))
-        .collect();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let hann_window = hann_window(&f32_samples);
+    #[test]
+    fn normalizes_samples() {
+        assert_eq!(i32::MIN.to_f32(), -1.0);
+        assert!((i32::MAX.to_f32() - 1.0).abs() < 1e-6);
+        assert_eq!(i16::MIN.to_f32(), -1.0);
 
-    Ok(samples_fft_to_spectrum(
-        &hann_window,
-        44100,
-        FrequencyLimit::All,
-        Some(&divide_by_N),
-    )?)
+        let mono: Vec<f32> = to_mono(&[i32::MIN, 0, 1 << 30, 1 << 30], 2).collect();
+        assert_eq!(mono, vec![-0.5, 0.5]);
+    }
 }
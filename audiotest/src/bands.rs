@@ -0,0 +1,182 @@
+use spectrum_analyzer::FrequencySpectrum;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum BandError {
+    #[error("Invalid frequency range {min}..{max} Hz")]
+    Range { min: f32, max: f32 },
+
+    #[error("No bands between {min} and {max} Hz")]
+    NoBands { min: f32, max: f32 },
+}
+
+/// How the frequency range is split into bands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BandScale {
+    /// This many bands, each the same number of octaves wide.
+    Log(usize),
+    /// The standard octave bands (centered on 1 kHz, 2 kHz, ...) that fit the range.
+    Octave,
+    /// The standard third-octave bands that fit the range.
+    ThirdOctave,
+    /// This many bands, each the same width on the mel scale, which follows how far apart
+    /// pitches sound.
+    Mel(usize),
+}
+
+/// Sums up a spectrum into a fixed number of bands, for visuals that want a handful of values
+/// instead of thousands of linear bins.
+#[derive(Debug, Clone)]
+pub struct BandMapper {
+    /// Band `i` covers `edges[i]..edges[i + 1]` Hz.
+    edges: Vec<f32>,
+}
+
+impl BandMapper {
+    /// Bands as `scale` says, between `min_frequency` and `max_frequency` Hz.
+    pub fn new(scale: BandScale, min_frequency: f32, max_frequency: f32) -> Result<BandMapper, BandError> {
+        let (min, max) = (min_frequency, max_frequency);
+        if !(min > 0.0 && min < max && max.is_finite()) {
+            return Err(BandError::Range { min, max });
+        }
+
+        let edges = match scale {
+            BandScale::Log(count) => spaced(count, min.log2(), max.log2(), f32::exp2),
+            BandScale::Octave => fractional_octave_edges(1, min, max),
+            BandScale::ThirdOctave => fractional_octave_edges(3, min, max),
+            BandScale::Mel(count) => spaced(count, hz_to_mel(min), hz_to_mel(max), mel_to_hz),
+        };
+        if edges.len() < 2 {
+            return Err(BandError::NoBands { min, max });
+        }
+        Ok(BandMapper { edges })
+    }
+
+    /// Number of bands, the length of every [`BandMapper::map`] result.
+    pub fn count(&self) -> usize {
+        self.edges.len() - 1
+    }
+
+    /// The average magnitude in every band. Bands too narrow to hold a bin, which happens at
+    /// the low end, get the bin closest to their center instead.
+    pub fn map(&self, spectrum: &FrequencySpectrum) -> Vec<f32> {
+        let data = spectrum.data();
+        let mut bands = Vec::with_capacity(self.count());
+        let mut bin = 0;
+
+        for edge in self.edges.windows(2) {
+            let (low, high) = (edge[0], edge[1]);
+            while bin < data.len() && data[bin].0.val() < low {
+                bin += 1;
+            }
+
+            let mut sum = 0.0;
+            let mut count = 0;
+            while bin < data.len() && data[bin].0.val() < high {
+                sum += data[bin].1.val();
+                count += 1;
+                bin += 1;
+            }
+
+            bands.push(if count > 0 {
+                sum / count as f32
+            } else if data.is_empty() {
+                0.0
+            } else {
+                spectrum.freq_val_closest((low * high).sqrt()).1.val()
+            });
+        }
+        bands
+    }
+}
+
+/// `count + 1` edges evenly spaced between `from` and `to` on some scale, brought back to Hz.
+fn spaced(count: usize, from: f32, to: f32, to_hz: fn(f32) -> f32) -> Vec<f32> {
+    if count == 0 {
+        return Vec::new();
+    }
+    (0..=count)
+        .map(|i| to_hz(from + (to - from) * i as f32 / count as f32))
+        .collect()
+}
+
+/// Edges of the 1/`fraction` octave bands, centered on 1 kHz and its multiples of
+/// 2^(1/`fraction`), whose centers fall between `min` and `max`.
+fn fractional_octave_edges(fraction: i32, min: f32, max: f32) -> Vec<f32> {
+    let first = (fraction as f32 * (min / 1000.0).log2()).ceil() as i32;
+    let last = (fraction as f32 * (max / 1000.0).log2()).floor() as i32;
+    if first > last {
+        return Vec::new();
+    }
+    // The edges lie half a band either side of the centers.
+    (first..=last + 1)
+        .map(|band| 1000.0 * ((band as f32 - 0.5) / fraction as f32).exp2())
+        .collect()
+}
+
+fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10f32.powf(mel / 2595.0) - 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::{Analyzer, AnalyzerConfig};
+    use std::f32::consts::PI;
+
+    /// Center frequency of every band, halfway between its edges on a log scale.
+    fn centers(mapper: &BandMapper) -> Vec<f32> {
+        mapper.edges.windows(2).map(|edge| (edge[0] * edge[1]).sqrt()).collect()
+    }
+
+    #[test]
+    fn band_layouts() {
+        let octaves = BandMapper::new(BandScale::Octave, 20.0, 20000.0).unwrap();
+        let expected = [31.25, 62.5, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0];
+        assert_eq!(octaves.count(), expected.len());
+        for (center, expected) in centers(&octaves).iter().zip(expected) {
+            assert!((center - expected).abs() < expected * 1e-4, "{} != {}", center, expected);
+        }
+        // 25 Hz up to 16 kHz; the 20 kHz band is centered just above the range.
+        assert_eq!(BandMapper::new(BandScale::ThirdOctave, 20.0, 20000.0).unwrap().count(), 29);
+
+        for scale in [BandScale::Log(24), BandScale::Mel(24)] {
+            let mapper = BandMapper::new(scale, 20.0, 20000.0).unwrap();
+            assert_eq!(mapper.count(), 24);
+            assert!((mapper.edges[0] - 20.0).abs() < 0.01);
+            assert!((mapper.edges[24] - 20000.0).abs() < 1.0);
+            assert!(mapper.edges.windows(2).all(|edge| edge[0] < edge[1]));
+        }
+
+        assert!(matches!(BandMapper::new(BandScale::Log(0), 20.0, 20000.0), Err(BandError::NoBands { .. })));
+        assert!(matches!(BandMapper::new(BandScale::Octave, 800.0, 900.0), Err(BandError::NoBands { .. })));
+        assert!(matches!(BandMapper::new(BandScale::Mel(8), 500.0, 20.0), Err(BandError::Range { .. })));
+    }
+
+    #[test]
+    fn sine_lands_in_its_band() {
+        let samples: Vec<f32> = (0..4096)
+            .map(|i| (2.0 * PI * 1000.0 * i as f32 / 48000.0).sin())
+            .collect();
+        let spectrum = Analyzer::new(AnalyzerConfig::default(), 48000).unwrap().analyze(&samples).unwrap();
+
+        let mapper = BandMapper::new(BandScale::Octave, 20.0, 20000.0).unwrap();
+        let bands = mapper.map(&spectrum);
+        assert_eq!(bands.len(), mapper.count());
+        let loudest = bands
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap()
+            .0;
+        assert!((centers(&mapper)[loudest] - 1000.0).abs() < 0.1);
+
+        // Third-octave bands at the low end are narrower than a bin, but still get a value.
+        let narrow = BandMapper::new(BandScale::ThirdOctave, 20.0, 100.0).unwrap();
+        assert!(narrow.map(&spectrum).iter().all(|band| band.is_finite()));
+    }
+}
@@ -0,0 +1,71 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// A triple buffer: the writer fills one slot, the reader looks at another, and the third holds
+// the newest complete value. Publishing and picking up a value are a single atomic swap each,
+// so neither side ever waits for the other.
+
+/// Set on the middle index when it holds a value the reader hasn't picked up yet.
+const FRESH: usize = 0b100;
+const INDEX: usize = 0b011;
+
+struct Shared<T> {
+    slots: [UnsafeCell<T>; 3],
+    middle: AtomicUsize,
+}
+
+// Each slot is only ever touched by whoever owns its index: the writer, the reader, or nobody
+// (the middle one).
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+/// Publishes values to a [`LatestReader`].
+pub struct LatestWriter<T> {
+    shared: Arc<Shared<T>>,
+    back: usize,
+}
+
+/// Reads the most recently published value.
+pub struct LatestReader<T> {
+    shared: Arc<Shared<T>>,
+    front: usize,
+}
+
+/// A cell holding the latest value, written by one thread and read by another, without locks.
+pub fn latest<T: Default + Send>() -> (LatestWriter<T>, LatestReader<T>) {
+    let shared = Arc::new(Shared {
+        slots: [UnsafeCell::default(), UnsafeCell::default(), UnsafeCell::default()],
+        middle: AtomicUsize::new(1),
+    });
+
+    let writer = LatestWriter {
+        shared: Arc::clone(&shared),
+        back: 0,
+    };
+    let reader = LatestReader { shared, front: 2 };
+    (writer, reader)
+}
+
+impl<T: Send> LatestWriter<T> {
+    pub fn write(&mut self, value: T) {
+        // SAFETY: The back slot belongs to the writer until it is swapped into the middle.
+        unsafe {
+            *self.shared.slots[self.back].get() = value;
+        }
+        let previous = self.shared.middle.swap(self.back | FRESH, Ordering::AcqRel);
+        self.back = previous & INDEX;
+    }
+}
+
+impl<T: Send> LatestReader<T> {
+    /// The newest value written so far, or the default if nothing was written yet.
+    pub fn read(&mut self) -> &T {
+        if self.shared.middle.load(Ordering::Relaxed) & FRESH != 0 {
+            let previous = self.shared.middle.swap(self.front, Ordering::AcqRel);
+            self.front = previous & INDEX;
+        }
+        // SAFETY: The front slot belongs to the reader until it is swapped into the middle,
+        // which only the reader does.
+        unsafe { &*self.shared.slots[self.front].get() }
+    }
+}
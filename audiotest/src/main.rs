@@ -5,8 +5,16 @@ use rayon::prelude::*;
 
 mod wave;
 
+mod latest;
+
+mod analyzer;
+use analyzer::AnalyzerConfig;
+
+mod bands;
+use bands::{BandError, BandMapper, BandScale};
+
 mod audio_spectrum;
-use audio_spectrum::Capturer;
+use audio_spectrum::{AudioFormat, Capturer};
 
 mod dot;
 use dot::{generate_dots, Dot};
@@ -22,15 +30,27 @@ pub struct Config {
     color_factor: f32,
     screen_clearing: f32,
     border_width: f32,
-    dot_mode: bool
+    dot_mode: bool,
+    band_scale: BandScale
 }
 
 struct AudioData {
-    volume_history: Vec<f32>
+    volume_history: Vec<f32>,
+    /// Sequence number of the last spectrum added to the history.
+    last_sequence: u64,
     //avg: f32,
     //count: usize
 }
 
+/// The range the frequency bands cover, as far as the sample rate allows.
+const MIN_BAND_FREQUENCY: f32 = 20.0;
+const MAX_BAND_FREQUENCY: f32 = 20000.0;
+
+fn band_mapper(scale: BandScale, format: AudioFormat) -> Result<BandMapper, BandError> {
+    let nyquist = format.sample_rate as f32 / 2.0;
+    BandMapper::new(scale, MIN_BAND_FREQUENCY, MAX_BAND_FREQUENCY.min(nyquist))
+}
+
 fn main() {
     nannou::app(model).update(update).run();
 }
@@ -39,6 +59,8 @@ struct Model {
     dots: Vec<Dot>,
     config: Config,
     spectrum_recorder: Capturer,
+    /// Splits the spectrum into bands, one for every group of dots.
+    band_mapper: BandMapper,
     gui: Egui,
     audio_data: AudioData,
     show_config: bool,
@@ -66,19 +88,29 @@ fn model(app: &App) -> Model {
         color_factor: 2.5,
         screen_clearing: 0.1,
         border_width: 5.0,
-        dot_mode: false
+        dot_mode: false,
+        band_scale: BandScale::Log(16)
     };
 
     let gui = Egui::from_window(&app.main_window());
 
+    let format = AudioFormat::default();
+
     Model {
         dots: generate_dots(&config, &screen),
-        spectrum_recorder: Capturer::new("Cool wavy dots".into(), 4096),
+        spectrum_recorder: Capturer::new(
+            "Cool wavy dots".into(),
+            AnalyzerConfig::default(),
+            format,
+        )
+        .expect("Failed to start capturing desktop audio"),
+        band_mapper: band_mapper(config.band_scale, format).expect("Invalid frequency bands"),
         config,
         gui,
         show_config: false,
         audio_data: AudioData {
-            volume_history: vec![]
+            volume_history: vec![],
+            last_sequence: 0
         }
     }
 }
@@ -114,22 +146,44 @@ fn update(app: &App, model: &mut Model, update: Update) {
     // Draw content (circles)
     let time = app.duration.since_start.as_secs_f32();
     let delta = update.since_last.as_secs_f32();
-    let spectrum = model.spectrum_recorder.get_spectrum();
-    let raw_volume: f32 = spectrum.average().val() + 1e-8; // 0.001 prevents divide by 0
-    let volume: f32 = spectrum.average().val() * model.config.color_factor * (1.0/average_raw_volume);
-    dbg!(volume);
-    let screen = app.window_rect();
-    model
-        .dots
-        .par_iter_mut()
-        .for_each(|dot| dot.update(&screen, time, volume, delta));
+    let analysis = match model.spectrum_recorder.latest_spectrum() {
+        Ok(analysis) => analysis,
+        Err(e) => {
+            eprintln!("Audio capture stopped: {}", e);
+            app.quit();
+            return;
+        }
+    };
+    // Nothing captured yet: leave the dots be, but still show the settings
+    if let Some(analysis) = analysis {
+        let raw_volume: f32 = analysis.spectrum.average().val() + 1e-8; // 0.001 prevents divide by 0
+        let volume: f32 = analysis.spectrum.average().val() * model.config.color_factor * (1.0/average_raw_volume);
+        // Every dot follows one band, louder or quieter than the spectrum as a whole
+        let bands = model.band_mapper.map(&analysis.spectrum);
+        let band_average = bands.iter().sum::<f32>() / bands.len() as f32 + 1e-8;
+        let is_new_spectrum = analysis.sequence != model.audio_data.last_sequence;
+        model.audio_data.last_sequence = analysis.sequence;
+        dbg!(volume);
+        let screen = app.window_rect();
+        model
+            .dots
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, dot)| {
+                let band_volume = volume * bands[i % bands.len()] / band_average;
+                dot.update(&screen, time, band_volume, delta)
+            });
+
+        if model.audio_data.volume_history.len() > 60*5 {
+            model.audio_data.volume_history.remove(0);
+        }
 
-    if model.audio_data.volume_history.len() > 60*5 {
-        model.audio_data.volume_history.remove(0);
+        // Only count each spectrum once, however often it's drawn
+        if is_new_spectrum {
+            model.audio_data.volume_history.push(raw_volume);
+        }
     }
 
-    model.audio_data.volume_history.push(raw_volume);
-
     // Get average raw volume;
     
     dbg!(average_raw_volume);
@@ -204,6 +258,22 @@ fn update(app: &App, model: &mut Model, update: Update) {
 
         ui.label("Dot mode");
         ui.add(Checkbox::new(&mut model.config.dot_mode, "Dot mode"));
+
+        ui.label("Frequency bands");
+        let band_scale = model.config.band_scale;
+        egui::ComboBox::from_id_source("Frequency bands")
+            .selected_text(format!("{:?}", band_scale))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut model.config.band_scale, BandScale::Log(16), "16 log-spaced");
+                ui.selectable_value(&mut model.config.band_scale, BandScale::Octave, "Octaves");
+                ui.selectable_value(&mut model.config.band_scale, BandScale::ThirdOctave, "Third octaves");
+                ui.selectable_value(&mut model.config.band_scale, BandScale::Mel(32), "32 mel");
+            });
+        if model.config.band_scale != band_scale {
+            if let Ok(band_mapper) = band_mapper(model.config.band_scale, model.spectrum_recorder.format()) {
+                model.band_mapper = band_mapper;
+            }
+        }
     });
 }
 